@@ -0,0 +1,117 @@
+//! Lexes a generated multi-megabyte file, run with `cargo bench --bench lexer`.
+//! The lexer borrows the source, so the heap it uses while lexing should stay tiny next to the file.
+//! Then lexes a file of about 100k tokens peeking before every token like `compile_ir` does,
+//! the peeked token is cached so peeking should cost at most one more lex per token, not grow with the file.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use sbl::{lexer::Lexer, token::TokenKind, tokenizer::Tokenizer};
+
+/// Wraps the system allocator to keep track of the most heap in use at once
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(current, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const LINES: usize = 30_000;
+
+/// Makes a program of about 4MB that uses most kinds of tokens, with some characters that are not ascii
+fn generate_source() -> String {
+    let mut source = String::new();
+    for i in 0..LINES {
+        source.push_str(&format!(
+            "proc p{i} (int) -> (int) {{ let x /* schön λ {i} */\n    x 0x{i:X} + 0b1010 * x_1_000 - dup {i} >= if {{ \"ü{i}\" drop }} }}\n"
+        ));
+    }
+    source
+}
+
+const RUNS: u32 = 5;
+
+/// Lexes all of `source`, peeking at each token first when `peek` is set, and returns the number of tokens
+fn lex_all(source: &str, peek: bool) -> usize {
+    let mut lexer = Lexer::new("generated.sbl".to_string(), source);
+    let mut token_count = 0;
+    loop {
+        if peek {
+            lexer.peek_kind().unwrap();
+            lexer.peek_token().unwrap();
+        }
+        if lexer.next_token().unwrap().kind == TokenKind::EndOfFile {
+            return token_count;
+        }
+        token_count += 1;
+    }
+}
+
+fn main() {
+    let source = generate_source();
+
+    let mut token_count = 0;
+    let mut peak_heap = 0;
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let baseline = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+
+        token_count = lex_all(&source, false);
+
+        peak_heap = PEAK.load(Ordering::Relaxed) - baseline;
+    }
+    let elapsed = start.elapsed() / RUNS;
+
+    let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:.1}MB, {} tokens: {:?} per run, {:.1}MB/s",
+        megabytes,
+        token_count,
+        elapsed,
+        megabytes / elapsed.as_secs_f64()
+    );
+    println!(
+        "peak heap while lexing: {} bytes, the source is {} bytes and a Vec<char> copy of it would be {} bytes",
+        peak_heap,
+        source.len(),
+        source.chars().count() * std::mem::size_of::<char>(),
+    );
+
+    // 14 tokens per line
+    let source = &source[..source.match_indices('\n').nth(100_000 / 14).unwrap().0];
+    for peek in [false, true] {
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            token_count = lex_all(source, peek);
+        }
+        let elapsed = start.elapsed() / RUNS;
+        println!(
+            "{} tokens {}: {:?} per run",
+            token_count,
+            if peek {
+                "with peeking"
+            } else {
+                "without peeking"
+            },
+            elapsed
+        );
+    }
+}
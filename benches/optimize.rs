@@ -0,0 +1,44 @@
+//! Times a loop summing 1 to 1,000,000 at each optimization level, run with `cargo bench`
+
+use std::{io::sink, time::Instant};
+
+use sbl::{
+    compile_str,
+    ir_execution::{run_ir_with, RunOptions},
+    optimize,
+};
+
+const SOURCE: &str = "
+proc sum(int) -> (int) {
+    let n
+    0 1 while dup n <= {
+        let i
+        i +
+        i 1 +
+    }
+    drop
+}
+1000000 sum call print
+";
+
+const RUNS: u32 = 5;
+
+fn main() {
+    for level in 0..=2 {
+        let mut procedures = compile_str("sum.sbl", SOURCE).unwrap().procedures;
+        optimize::run(&mut procedures, level);
+        let instruction_count: usize = procedures.iter().map(Vec::len).sum();
+
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            let mut out = sink();
+            run_ir_with(&procedures, &mut RunOptions::new(&mut out)).unwrap();
+        }
+        let elapsed = start.elapsed() / RUNS;
+
+        println!(
+            "-O{}: {} instructions, {:?} per run",
+            level, instruction_count, elapsed
+        );
+    }
+}
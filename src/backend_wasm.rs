@@ -0,0 +1,545 @@
+use std::fmt::Write;
+
+use crate::{
+    common::Error,
+    ir::{get_local_count, get_memory_size, IRKind, IR},
+};
+
+const DATA_STACK_SIZE: usize = 1024 * 1024;
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Generates a WebAssembly text module, every procedure becomes a function `$procN` in a function table.
+/// Values are kept on a data stack in linear memory after the memory regions, so calls through the table
+/// can all share the type `(func)`, arithmetic is still done on the WebAssembly operand stack.
+/// Jumps are turned into a `loop` that dispatches with `br_table` to the basic block in `$pc`.
+pub fn emit_wat(procedures: &[Vec<IR>]) -> Result<String, Error> {
+    let mut output = String::new();
+
+    let memory_size = get_memory_size(procedures).div_ceil(8) * 8;
+    let data_stack_end = memory_size + DATA_STACK_SIZE;
+    let pages = data_stack_end.div_ceil(PAGE_SIZE);
+
+    writeln!(output, ";; Generated by sbl").unwrap();
+    writeln!(output, ";;").unwrap();
+    writeln!(output, ";; The host has to provide:").unwrap();
+    writeln!(
+        output,
+        ";;     env.print (i64), prints a value followed by a newline"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.emit (i64), prints a value without a newline"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.print_hex (i64), prints a value in base 16 followed by a newline"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.print_bin (i64), prints a value in base 2 followed by a newline"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.print_str (i32 i32), prints bytes from the exported memory"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.exit (i64), stops the program with an exit code and does not return"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        ";;     env.dump_stack (i32 i32), prints the count and then the values of the data stack from the bottom up"
+    )
+    .unwrap();
+    writeln!(output, "(module").unwrap();
+    writeln!(output, "  (type $proc (func))").unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"print\" (func $print (param i64)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"print_hex\" (func $print_hex (param i64)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"print_bin\" (func $print_bin (param i64)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"emit\" (func $emit (param i64)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"print_str\" (func $print_str (param i32 i32)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"exit\" (func $exit (param i64)))"
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (import \"env\" \"dump_stack\" (func $dump_stack (param i32 i32)))"
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+
+    // the memory regions start at address 0, the data stack grows down from the end of memory
+    writeln!(output, "  (memory (export \"memory\") {})", pages).unwrap();
+    writeln!(
+        output,
+        "  (global $sp (mut i32) (i32.const {}))",
+        data_stack_end
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  (global $data_stack_end i32 (i32.const {}))",
+        data_stack_end
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "  (table {} funcref)", procedures.len()).unwrap();
+    write!(output, "  (elem (i32.const 0)").unwrap();
+    for id in 0..procedures.len() {
+        write!(output, " $proc{}", id).unwrap();
+    }
+    writeln!(output, ")").unwrap();
+    writeln!(output, "  (export \"_start\" (func $proc0))").unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "  (func $push (param $value i64)").unwrap();
+    writeln!(output, "    global.get $sp").unwrap();
+    writeln!(output, "    i32.const 8").unwrap();
+    writeln!(output, "    i32.sub").unwrap();
+    writeln!(output, "    global.set $sp").unwrap();
+    writeln!(output, "    global.get $sp").unwrap();
+    writeln!(output, "    local.get $value").unwrap();
+    writeln!(output, "    i64.store)").unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "  (func $pop (result i64)").unwrap();
+    writeln!(output, "    global.get $sp").unwrap();
+    writeln!(output, "    i64.load").unwrap();
+    writeln!(output, "    global.get $sp").unwrap();
+    writeln!(output, "    i32.const 8").unwrap();
+    writeln!(output, "    i32.add").unwrap();
+    writeln!(output, "    global.set $sp)").unwrap();
+    writeln!(output).unwrap();
+
+    for (id, procedure) in procedures.iter().enumerate() {
+        emit_procedure(&mut output, id, procedure)?;
+        writeln!(output).unwrap();
+    }
+
+    writeln!(output, ")").unwrap();
+
+    Ok(output)
+}
+
+/// Gets the sorted positions where a basic block of `procedure` starts, which is the start,
+/// every jump target and every instruction after a jump
+fn get_block_starts(procedure: &[IR]) -> Vec<usize> {
+    let mut starts = vec![0];
+    for (position, ir) in procedure.iter().enumerate() {
+        match ir.kind {
+            IRKind::Jump { relative_position }
+            | IRKind::JumpFalse { relative_position }
+            | IRKind::JumpTrue { relative_position } => {
+                starts.push((position as isize + relative_position) as usize);
+                starts.push(position + 1);
+            }
+            _ => {}
+        }
+    }
+    starts.sort_unstable();
+    starts.dedup();
+    starts
+}
+
+/// Writes the function `$procN` for the procedure `id`, with a local for each of its local slots
+/// and a block for each of its basic blocks
+fn emit_procedure(output: &mut String, id: usize, procedure: &[IR]) -> Result<(), Error> {
+    let block_starts = get_block_starts(procedure);
+
+    write!(output, "  (func $proc{} (type $proc)", id).unwrap();
+    write!(
+        output,
+        " (local $pc i32) (local $a i64) (local $b i64) (local $c i64)"
+    )
+    .unwrap();
+    for slot in 0..get_local_count(procedure) {
+        write!(output, " (local $l{} i64)", slot).unwrap();
+    }
+    writeln!(output).unwrap();
+
+    // the code of block N comes right after the end of `$bN`, so breaking to `$bN` runs it
+    // and every block falls through into the next one
+    writeln!(output, "    loop $dispatch").unwrap();
+    for block in (0..block_starts.len()).rev() {
+        writeln!(output, "    block $b{}", block).unwrap();
+    }
+    writeln!(output, "    local.get $pc").unwrap();
+    write!(output, "    br_table").unwrap();
+    for block in 0..block_starts.len() {
+        write!(output, " $b{}", block).unwrap();
+    }
+    writeln!(output).unwrap();
+
+    for (position, ir) in procedure.iter().enumerate() {
+        if let Ok(block) = block_starts.binary_search(&position) {
+            writeln!(output, "    end ;; $b{}", block).unwrap();
+        }
+        writeln!(output, "    ;; {:?}", ir.kind).unwrap();
+        emit_instruction(output, position, ir, &block_starts)?;
+    }
+    if let Ok(block) = block_starts.binary_search(&procedure.len()) {
+        writeln!(output, "    end ;; $b{}", block).unwrap();
+    }
+
+    writeln!(output, "    end)").unwrap();
+    Ok(())
+}
+
+fn emit_binary_operation(output: &mut String, instructions: &[&str]) {
+    writeln!(output, "    call $pop").unwrap();
+    writeln!(output, "    local.set $b").unwrap();
+    writeln!(output, "    call $pop").unwrap();
+    writeln!(output, "    local.get $b").unwrap();
+    for instruction in instructions {
+        writeln!(output, "    {}", instruction).unwrap();
+    }
+    writeln!(output, "    call $push").unwrap();
+}
+
+fn emit_comparison(output: &mut String, instruction: &str) {
+    emit_binary_operation(output, &[instruction, "i64.extend_i32_u"]);
+}
+
+fn emit_select(output: &mut String, comparison: &str) {
+    writeln!(output, "    call $pop").unwrap();
+    writeln!(output, "    local.set $b").unwrap();
+    writeln!(output, "    call $pop").unwrap();
+    writeln!(output, "    local.set $a").unwrap();
+    writeln!(output, "    local.get $a").unwrap();
+    writeln!(output, "    local.get $b").unwrap();
+    writeln!(output, "    local.get $a").unwrap();
+    writeln!(output, "    local.get $b").unwrap();
+    writeln!(output, "    {}", comparison).unwrap();
+    writeln!(output, "    select").unwrap();
+    writeln!(output, "    call $push").unwrap();
+}
+
+/// Pops values into the locals in `pops` order, then pushes the locals in `pushes` order
+fn emit_shuffle(output: &mut String, pops: &[&str], pushes: &[&str]) {
+    for local in pops {
+        writeln!(output, "    call $pop").unwrap();
+        writeln!(output, "    local.set ${}", local).unwrap();
+    }
+    for local in pushes {
+        writeln!(output, "    local.get ${}", local).unwrap();
+        writeln!(output, "    call $push").unwrap();
+    }
+}
+
+fn emit_jump(output: &mut String, target: isize, block_starts: &[usize]) {
+    let block = block_starts.binary_search(&(target as usize)).unwrap();
+    writeln!(output, "    i32.const {}", block).unwrap();
+    writeln!(output, "    local.set $pc").unwrap();
+    writeln!(output, "    br $dispatch").unwrap();
+}
+
+fn emit_instruction(
+    output: &mut String,
+    position: usize,
+    ir: &IR,
+    block_starts: &[usize],
+) -> Result<(), Error> {
+    match &ir.kind {
+        // only procedure 0 exits and it is only called by the host
+        IRKind::Exit => {
+            writeln!(output, "    return").unwrap();
+        }
+
+        IRKind::PushProc { id } => {
+            writeln!(output, "    i64.const {}", id).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::PushInt { value } => {
+            writeln!(output, "    i64.const {}", value).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::PushFloat { value: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Float values can not be compiled to WebAssembly".to_string(),
+            })
+        }
+
+        IRKind::PushBool { value } => {
+            writeln!(output, "    i64.const {}", *value as u8).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::PushType { typ: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Type values can not be compiled to WebAssembly".to_string(),
+            })
+        }
+
+        IRKind::PushMemory { offset, size: _ } => {
+            writeln!(output, "    i64.const {}", offset).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::PushConst { index: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Pooled constants can not be compiled to WebAssembly".to_string(),
+            })
+        }
+
+        IRKind::Add => emit_binary_operation(output, &["i64.add"]),
+        IRKind::Sub => emit_binary_operation(output, &["i64.sub"]),
+        IRKind::Mul => emit_binary_operation(output, &["i64.mul"]),
+        IRKind::Div => emit_binary_operation(output, &["i64.div_s"]),
+        IRKind::Mod => emit_binary_operation(output, &["i64.rem_s"]),
+        IRKind::BitAnd => emit_binary_operation(output, &["i64.and"]),
+        IRKind::BitOr => emit_binary_operation(output, &["i64.or"]),
+        IRKind::BitXor => emit_binary_operation(output, &["i64.xor"]),
+        IRKind::ShiftLeft => emit_binary_operation(output, &["i64.shl"]),
+        IRKind::ShiftRight => emit_binary_operation(output, &["i64.shr_s"]),
+
+        IRKind::LessThan => emit_comparison(output, "i64.lt_s"),
+        IRKind::GreaterThan => emit_comparison(output, "i64.gt_s"),
+        IRKind::LessThanEqual => emit_comparison(output, "i64.le_s"),
+        IRKind::GreaterThanEqual => emit_comparison(output, "i64.ge_s"),
+        IRKind::Equal => emit_comparison(output, "i64.eq"),
+        IRKind::NotEqual => emit_comparison(output, "i64.ne"),
+
+        IRKind::Not => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i64.eqz").unwrap();
+            writeln!(output, "    i64.extend_i32_u").unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::And => emit_binary_operation(output, &["i64.and"]),
+        IRKind::Or => emit_binary_operation(output, &["i64.or"]),
+        IRKind::Max => emit_select(output, "i64.gt_s"),
+        IRKind::Min => emit_select(output, "i64.lt_s"),
+
+        IRKind::Neg => {
+            writeln!(output, "    i64.const 0").unwrap();
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i64.sub").unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Dup => {
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i64.load").unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Drop => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    drop").unwrap();
+        }
+
+        IRKind::Swap => emit_shuffle(output, &["b", "a"], &["b", "a"]),
+
+        IRKind::Over => {
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i64.load offset=8").unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Rot => emit_shuffle(output, &["c", "b", "a"], &["b", "c", "a"]),
+
+        IRKind::TwoDup => {
+            // after the first push the second value has moved to the same offset
+            for _ in 0..2 {
+                writeln!(output, "    global.get $sp").unwrap();
+                writeln!(output, "    i64.load offset=8").unwrap();
+                writeln!(output, "    call $push").unwrap();
+            }
+        }
+
+        IRKind::Nip => emit_shuffle(output, &["b", "a"], &["b"]),
+        IRKind::Tuck => emit_shuffle(output, &["b", "a"], &["b", "a", "b"]),
+
+        IRKind::DropN { count } => {
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i32.const {}", count * 8).unwrap();
+            writeln!(output, "    i32.add").unwrap();
+            writeln!(output, "    global.set $sp").unwrap();
+        }
+
+        IRKind::DupN { count } => {
+            // after each push the next value to copy has moved to the same offset
+            for _ in 0..*count {
+                writeln!(output, "    global.get $sp").unwrap();
+                writeln!(output, "    i64.load offset={}", (count - 1) * 8).unwrap();
+                writeln!(output, "    call $push").unwrap();
+            }
+        }
+
+        IRKind::Pick { depth } => {
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i64.load offset={}", depth * 8).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Roll { depth } => {
+            // shift everything above the rolled value down by one slot, then put it on top
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i64.load offset={}", depth * 8).unwrap();
+            writeln!(output, "    local.set $a").unwrap();
+            for i in (0..*depth).rev() {
+                writeln!(output, "    global.get $sp").unwrap();
+                writeln!(output, "    global.get $sp").unwrap();
+                writeln!(output, "    i64.load offset={}", i * 8).unwrap();
+                writeln!(output, "    i64.store offset={}", (i + 1) * 8).unwrap();
+            }
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    local.get $a").unwrap();
+            writeln!(output, "    i64.store").unwrap();
+        }
+
+        IRKind::StoreLocal { slot } => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    local.set $l{}", slot).unwrap();
+        }
+
+        IRKind::LoadLocal { slot } => {
+            writeln!(output, "    local.get $l{}", slot).unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Load => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    i64.load").unwrap();
+            writeln!(output, "    call $push").unwrap();
+        }
+
+        IRKind::Store => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    local.set $b").unwrap();
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    local.get $b").unwrap();
+            writeln!(output, "    i64.store").unwrap();
+        }
+
+        IRKind::Jump { relative_position } => {
+            emit_jump(output, position as isize + relative_position, block_starts);
+        }
+
+        IRKind::JumpFalse { relative_position } => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i64.eqz").unwrap();
+            writeln!(output, "    if").unwrap();
+            emit_jump(output, position as isize + relative_position, block_starts);
+            writeln!(output, "    end").unwrap();
+        }
+
+        IRKind::JumpTrue { relative_position } => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    if").unwrap();
+            emit_jump(output, position as isize + relative_position, block_starts);
+            writeln!(output, "    end").unwrap();
+        }
+
+        IRKind::Call => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    call_indirect (type $proc)").unwrap();
+        }
+
+        IRKind::TailCall => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    call_indirect (type $proc)").unwrap();
+            writeln!(output, "    return").unwrap();
+        }
+
+        IRKind::Return => {
+            writeln!(output, "    return").unwrap();
+        }
+
+        IRKind::Print => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    call $print").unwrap();
+        }
+
+        IRKind::PrintHex => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    call $print_hex").unwrap();
+        }
+
+        IRKind::PrintBin => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    call $print_bin").unwrap();
+        }
+
+        IRKind::Emit => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    call $emit").unwrap();
+        }
+
+        IRKind::Assert => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i64.eqz").unwrap();
+            writeln!(output, "    if").unwrap();
+            writeln!(output, "    unreachable").unwrap();
+            writeln!(output, "    end").unwrap();
+        }
+
+        IRKind::DumpStack => {
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    global.get $data_stack_end").unwrap();
+            writeln!(output, "    global.get $sp").unwrap();
+            writeln!(output, "    i32.sub").unwrap();
+            writeln!(output, "    i32.const 8").unwrap();
+            writeln!(output, "    i32.div_u").unwrap();
+            writeln!(output, "    call $dump_stack").unwrap();
+        }
+
+        // the host is not expected to return from `exit`
+        IRKind::ExitWithCode => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    call $exit").unwrap();
+            writeln!(output, "    unreachable").unwrap();
+        }
+
+        IRKind::PrintStr => {
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    local.set $b").unwrap();
+            writeln!(output, "    call $pop").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    local.get $b").unwrap();
+            writeln!(output, "    i32.wrap_i64").unwrap();
+            writeln!(output, "    call $print_str").unwrap();
+        }
+    }
+    Ok(())
+}
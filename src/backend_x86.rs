@@ -0,0 +1,531 @@
+use std::fmt::Write;
+
+use crate::{
+    common::Error,
+    ir::{get_local_count, get_memory_size, IRKind, IR},
+};
+
+const DATA_STACK_SIZE: usize = 1024 * 1024;
+
+/// Generates NASM assembly for x86-64 Linux, every procedure becomes a label `procN`.
+/// Values are kept on a separate data stack pointed to by `r15`, so `call`/`ret` can use the hardware stack.
+/// The locals of a procedure call live in a frame on the hardware stack, addressed from `rsp`.
+pub fn emit_nasm(procedures: &[Vec<IR>]) -> Result<String, Error> {
+    let mut output = String::new();
+
+    writeln!(output, "; Generated by sbl").unwrap();
+    writeln!(output, ";").unwrap();
+    writeln!(output, "; To assemble and link:").unwrap();
+    writeln!(output, ";     nasm -f elf64 output.asm -o output.o").unwrap();
+    writeln!(
+        output,
+        ";     ld output.o -o output -lc -dynamic-linker /lib64/ld-linux-x86-64.so.2"
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "%macro PUSH_RAX 0").unwrap();
+    writeln!(output, "    sub r15, 8").unwrap();
+    writeln!(output, "    mov [r15], rax").unwrap();
+    writeln!(output, "%endmacro").unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "%macro POP_RAX 0").unwrap();
+    writeln!(output, "    mov rax, [r15]").unwrap();
+    writeln!(output, "    add r15, 8").unwrap();
+    writeln!(output, "%endmacro").unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "%macro POP_RBX 0").unwrap();
+    writeln!(output, "    mov rbx, [r15]").unwrap();
+    writeln!(output, "    add r15, 8").unwrap();
+    writeln!(output, "%endmacro").unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "extern printf").unwrap();
+    writeln!(output, "extern exit").unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "section .text").unwrap();
+    writeln!(output, "global _start").unwrap();
+    writeln!(output, "_start:").unwrap();
+    writeln!(output, "    mov r15, data_stack_end").unwrap();
+    writeln!(output, "    jmp proc0").unwrap();
+    writeln!(output).unwrap();
+
+    // prints rax followed by a newline, bools are printed as 0 or 1
+    // the hardware stack has to be aligned for printf
+    writeln!(output, "sbl_print:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, print_format").unwrap();
+    writeln!(output, "    mov rsi, rax").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rsp, rbp").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    // prints rax without a newline
+    writeln!(output, "sbl_emit:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, emit_format").unwrap();
+    writeln!(output, "    mov rsi, rax").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rsp, rbp").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    // prints rax in base 16, negative values are printed as a minus sign and their magnitude
+    writeln!(output, "sbl_print_hex:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, print_hex_format").unwrap();
+    writeln!(output, "    mov rsi, empty_string").unwrap();
+    writeln!(output, "    mov rdx, rax").unwrap();
+    writeln!(output, "    test rax, rax").unwrap();
+    writeln!(output, "    jns .positive").unwrap();
+    writeln!(output, "    mov rsi, minus_sign").unwrap();
+    writeln!(output, "    neg rdx").unwrap();
+    writeln!(output, ".positive:").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rsp, rbp").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    // prints rax in base 2, printf has no format for it so the digits are written backwards into a buffer
+    writeln!(output, "sbl_print_bin:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    sub rsp, 80").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rsi, empty_string").unwrap();
+    writeln!(output, "    mov rdx, rax").unwrap();
+    writeln!(output, "    test rax, rax").unwrap();
+    writeln!(output, "    jns .positive").unwrap();
+    writeln!(output, "    mov rsi, minus_sign").unwrap();
+    writeln!(output, "    neg rdx").unwrap();
+    writeln!(output, ".positive:").unwrap();
+    writeln!(output, "    lea rcx, [rbp - 1]").unwrap();
+    writeln!(output, "    mov byte [rcx], 0").unwrap();
+    writeln!(output, ".digit:").unwrap();
+    writeln!(output, "    dec rcx").unwrap();
+    writeln!(output, "    mov r8, rdx").unwrap();
+    writeln!(output, "    and r8, 1").unwrap();
+    writeln!(output, "    add r8, '0'").unwrap();
+    writeln!(output, "    mov [rcx], r8b").unwrap();
+    writeln!(output, "    shr rdx, 1").unwrap();
+    writeln!(output, "    jnz .digit").unwrap();
+    writeln!(output, "    mov rdx, rcx").unwrap();
+    writeln!(output, "    mov rdi, print_bin_format").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rsp, rbp").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    // prints the number of values on the data stack and then the values from the bottom up, rbx is saved because
+    // printf does not have to keep it
+    writeln!(output, "sbl_dump_stack:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    push rbx").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, dump_stack_format").unwrap();
+    writeln!(output, "    mov rsi, data_stack_end").unwrap();
+    writeln!(output, "    sub rsi, r15").unwrap();
+    writeln!(output, "    shr rsi, 3").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rbx, data_stack_end").unwrap();
+    writeln!(output, ".value:").unwrap();
+    writeln!(output, "    cmp rbx, r15").unwrap();
+    writeln!(output, "    je .done").unwrap();
+    writeln!(output, "    sub rbx, 8").unwrap();
+    writeln!(output, "    mov rdi, dump_stack_value_format").unwrap();
+    writeln!(output, "    mov rsi, [rbx]").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    jmp .value").unwrap();
+    writeln!(output, ".done:").unwrap();
+    writeln!(output, "    mov rdi, newline").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    lea rsp, [rbp - 8]").unwrap();
+    writeln!(output, "    pop rbx").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    // prints a message and exits with a non zero status, never returns
+    writeln!(output, "sbl_assert_failed:").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, assert_message").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov edi, 1").unwrap();
+    writeln!(output, "    call exit").unwrap();
+    writeln!(output).unwrap();
+
+    // prints rbx bytes starting at the address in rax
+    writeln!(output, "sbl_print_str:").unwrap();
+    writeln!(output, "    push rbp").unwrap();
+    writeln!(output, "    mov rbp, rsp").unwrap();
+    writeln!(output, "    and rsp, -16").unwrap();
+    writeln!(output, "    mov rdi, print_str_format").unwrap();
+    writeln!(output, "    mov rsi, rbx").unwrap();
+    writeln!(output, "    mov rdx, rax").unwrap();
+    writeln!(output, "    xor eax, eax").unwrap();
+    writeln!(output, "    call printf").unwrap();
+    writeln!(output, "    mov rsp, rbp").unwrap();
+    writeln!(output, "    pop rbp").unwrap();
+    writeln!(output, "    ret").unwrap();
+    writeln!(output).unwrap();
+
+    for (id, procedure) in procedures.iter().enumerate() {
+        let frame_size = get_frame_size(procedure);
+        writeln!(output, "proc{}:", id).unwrap();
+        if frame_size > 0 {
+            writeln!(output, "    sub rsp, {}", frame_size).unwrap();
+        }
+        for (position, ir) in procedure.iter().enumerate() {
+            writeln!(output, ".i{}: ; {:?}", position, ir.kind).unwrap();
+            emit_instruction(&mut output, position, ir, frame_size)?;
+        }
+        writeln!(output).unwrap();
+    }
+
+    writeln!(output, "section .data").unwrap();
+    writeln!(output, "print_format: db \"%lld\", 10, 0").unwrap();
+    writeln!(output, "print_str_format: db \"%.*s\", 0").unwrap();
+    writeln!(output, "emit_format: db \"%lld\", 0").unwrap();
+    writeln!(output, "print_hex_format: db \"%s0x%llx\", 10, 0").unwrap();
+    writeln!(output, "print_bin_format: db \"%s0b%s\", 10, 0").unwrap();
+    writeln!(output, "dump_stack_format: db \"<%lld>\", 0").unwrap();
+    writeln!(output, "dump_stack_value_format: db \" %lld\", 0").unwrap();
+    writeln!(output, "newline: db 10, 0").unwrap();
+    writeln!(output, "minus_sign: db \"-\", 0").unwrap();
+    writeln!(output, "empty_string: db 0").unwrap();
+    writeln!(output, "assert_message: db \"Assertion failed\", 10, 0").unwrap();
+    writeln!(output).unwrap();
+
+    writeln!(output, "section .bss").unwrap();
+    writeln!(output, "data_stack: resq {}", DATA_STACK_SIZE).unwrap();
+    writeln!(output, "data_stack_end:").unwrap();
+    let memory_size = get_memory_size(procedures);
+    if memory_size > 0 {
+        writeln!(output, "memory: resb {}", memory_size).unwrap();
+    }
+
+    Ok(output)
+}
+
+/// Gets the number of bytes needed for the locals of `procedure`
+fn get_frame_size(procedure: &[IR]) -> usize {
+    get_local_count(procedure) * 8
+}
+
+fn emit_binary_operation(output: &mut String, instructions: &[&str]) {
+    writeln!(output, "    POP_RBX").unwrap();
+    writeln!(output, "    POP_RAX").unwrap();
+    for instruction in instructions {
+        writeln!(output, "    {}", instruction).unwrap();
+    }
+    writeln!(output, "    PUSH_RAX").unwrap();
+}
+
+fn emit_comparison(output: &mut String, set_instruction: &str) {
+    writeln!(output, "    POP_RBX").unwrap();
+    writeln!(output, "    POP_RAX").unwrap();
+    writeln!(output, "    cmp rax, rbx").unwrap();
+    writeln!(output, "    {} al", set_instruction).unwrap();
+    writeln!(output, "    movzx rax, al").unwrap();
+    writeln!(output, "    PUSH_RAX").unwrap();
+}
+
+fn emit_instruction(
+    output: &mut String,
+    position: usize,
+    ir: &IR,
+    frame_size: usize,
+) -> Result<(), Error> {
+    match &ir.kind {
+        IRKind::Exit => {
+            writeln!(output, "    and rsp, -16").unwrap();
+            writeln!(output, "    xor edi, edi").unwrap();
+            writeln!(output, "    call exit").unwrap();
+        }
+
+        IRKind::PushProc { id } => {
+            writeln!(output, "    mov rax, proc{}", id).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::PushInt { value } => {
+            writeln!(output, "    mov rax, {}", value).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::PushFloat { value: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Float values can not be compiled to assembly".to_string(),
+            })
+        }
+
+        IRKind::PushBool { value } => {
+            writeln!(output, "    mov rax, {}", *value as u8).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::PushType { typ: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Type values can not be compiled to assembly".to_string(),
+            })
+        }
+
+        IRKind::PushMemory { offset, size: _ } => {
+            writeln!(output, "    mov rax, memory + {}", offset).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::PushConst { index: _ } => {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: "Pooled constants can not be compiled to assembly".to_string(),
+            })
+        }
+
+        IRKind::Add => emit_binary_operation(output, &["add rax, rbx"]),
+        IRKind::Sub => emit_binary_operation(output, &["sub rax, rbx"]),
+        IRKind::Mul => emit_binary_operation(output, &["imul rax, rbx"]),
+        IRKind::Div => emit_binary_operation(output, &["cqo", "idiv rbx"]),
+        IRKind::Mod => emit_binary_operation(output, &["cqo", "idiv rbx", "mov rax, rdx"]),
+        IRKind::BitAnd => emit_binary_operation(output, &["and rax, rbx"]),
+        IRKind::BitOr => emit_binary_operation(output, &["or rax, rbx"]),
+        IRKind::BitXor => emit_binary_operation(output, &["xor rax, rbx"]),
+        IRKind::ShiftLeft => emit_binary_operation(output, &["mov rcx, rbx", "shl rax, cl"]),
+        IRKind::ShiftRight => emit_binary_operation(output, &["mov rcx, rbx", "sar rax, cl"]),
+
+        IRKind::LessThan => emit_comparison(output, "setl"),
+        IRKind::GreaterThan => emit_comparison(output, "setg"),
+        IRKind::LessThanEqual => emit_comparison(output, "setle"),
+        IRKind::GreaterThanEqual => emit_comparison(output, "setge"),
+        IRKind::Equal => emit_comparison(output, "sete"),
+        IRKind::NotEqual => emit_comparison(output, "setne"),
+
+        IRKind::Not => {
+            writeln!(output, "    xor qword [r15], 1").unwrap();
+        }
+
+        IRKind::And => emit_binary_operation(output, &["and rax, rbx"]),
+        IRKind::Or => emit_binary_operation(output, &["or rax, rbx"]),
+        IRKind::Max => emit_binary_operation(output, &["cmp rax, rbx", "cmovl rax, rbx"]),
+        IRKind::Min => emit_binary_operation(output, &["cmp rax, rbx", "cmovg rax, rbx"]),
+
+        IRKind::Neg => {
+            writeln!(output, "    neg qword [r15]").unwrap();
+        }
+
+        IRKind::Dup => {
+            writeln!(output, "    mov rax, [r15]").unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::Drop => {
+            writeln!(output, "    add r15, 8").unwrap();
+        }
+
+        IRKind::Swap => {
+            writeln!(output, "    mov rax, [r15]").unwrap();
+            writeln!(output, "    mov rbx, [r15 + 8]").unwrap();
+            writeln!(output, "    mov [r15], rbx").unwrap();
+            writeln!(output, "    mov [r15 + 8], rax").unwrap();
+        }
+
+        IRKind::Over => {
+            writeln!(output, "    mov rax, [r15 + 8]").unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::Rot => {
+            writeln!(output, "    mov rax, [r15 + 16]").unwrap();
+            writeln!(output, "    mov rbx, [r15 + 8]").unwrap();
+            writeln!(output, "    mov rcx, [r15]").unwrap();
+            writeln!(output, "    mov [r15 + 16], rbx").unwrap();
+            writeln!(output, "    mov [r15 + 8], rcx").unwrap();
+            writeln!(output, "    mov [r15], rax").unwrap();
+        }
+
+        IRKind::TwoDup => {
+            writeln!(output, "    mov rax, [r15 + 8]").unwrap();
+            writeln!(output, "    mov rbx, [r15]").unwrap();
+            writeln!(output, "    sub r15, 16").unwrap();
+            writeln!(output, "    mov [r15 + 8], rax").unwrap();
+            writeln!(output, "    mov [r15], rbx").unwrap();
+        }
+
+        IRKind::Nip => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    mov [r15], rax").unwrap();
+        }
+
+        IRKind::Tuck => {
+            writeln!(output, "    mov rax, [r15]").unwrap();
+            writeln!(output, "    mov rbx, [r15 + 8]").unwrap();
+            writeln!(output, "    sub r15, 8").unwrap();
+            writeln!(output, "    mov [r15 + 16], rax").unwrap();
+            writeln!(output, "    mov [r15 + 8], rbx").unwrap();
+            writeln!(output, "    mov [r15], rax").unwrap();
+        }
+
+        IRKind::DropN { count } => {
+            writeln!(output, "    add r15, {}", count * 8).unwrap();
+        }
+
+        IRKind::DupN { count } => {
+            // after each push the next value to copy has moved to the same offset
+            for _ in 0..*count {
+                writeln!(output, "    mov rax, [r15 + {}]", (count - 1) * 8).unwrap();
+                writeln!(output, "    PUSH_RAX").unwrap();
+            }
+        }
+
+        IRKind::Pick { depth } => {
+            writeln!(output, "    mov rax, [r15 + {}]", depth * 8).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::Roll { depth } => {
+            // shift everything above the rolled value down by one slot, then put it on top
+            writeln!(output, "    mov rax, [r15 + {}]", depth * 8).unwrap();
+            for i in (0..*depth).rev() {
+                writeln!(output, "    mov rbx, [r15 + {}]", i * 8).unwrap();
+                writeln!(output, "    mov [r15 + {}], rbx", (i + 1) * 8).unwrap();
+            }
+            writeln!(output, "    mov [r15], rax").unwrap();
+        }
+
+        IRKind::StoreLocal { slot } => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    mov [rsp + {}], rax", slot * 8).unwrap();
+        }
+
+        IRKind::LoadLocal { slot } => {
+            writeln!(output, "    mov rax, [rsp + {}]", slot * 8).unwrap();
+            writeln!(output, "    PUSH_RAX").unwrap();
+        }
+
+        IRKind::Load => {
+            writeln!(output, "    mov rax, [r15]").unwrap();
+            writeln!(output, "    mov rax, [rax]").unwrap();
+            writeln!(output, "    mov [r15], rax").unwrap();
+        }
+
+        IRKind::Store => {
+            writeln!(output, "    POP_RBX").unwrap();
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    mov [rax], rbx").unwrap();
+        }
+
+        IRKind::Jump { relative_position } => {
+            writeln!(
+                output,
+                "    jmp .i{}",
+                position as isize + relative_position
+            )
+            .unwrap();
+        }
+
+        IRKind::JumpFalse { relative_position } => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    test rax, rax").unwrap();
+            writeln!(output, "    jz .i{}", position as isize + relative_position).unwrap();
+        }
+
+        IRKind::JumpTrue { relative_position } => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    test rax, rax").unwrap();
+            writeln!(
+                output,
+                "    jnz .i{}",
+                position as isize + relative_position
+            )
+            .unwrap();
+        }
+
+        IRKind::Call => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call rax").unwrap();
+        }
+
+        IRKind::TailCall => {
+            writeln!(output, "    POP_RAX").unwrap();
+            if frame_size > 0 {
+                writeln!(output, "    add rsp, {}", frame_size).unwrap();
+            }
+            writeln!(output, "    jmp rax").unwrap();
+        }
+
+        IRKind::Return => {
+            if frame_size > 0 {
+                writeln!(output, "    add rsp, {}", frame_size).unwrap();
+            }
+            writeln!(output, "    ret").unwrap();
+        }
+
+        IRKind::Print => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call sbl_print").unwrap();
+        }
+
+        IRKind::PrintHex => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call sbl_print_hex").unwrap();
+        }
+
+        IRKind::PrintBin => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call sbl_print_bin").unwrap();
+        }
+
+        IRKind::Emit => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call sbl_emit").unwrap();
+        }
+
+        IRKind::Assert => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    test rax, rax").unwrap();
+            writeln!(output, "    jnz .i{}", position + 1).unwrap();
+            writeln!(output, "    call sbl_assert_failed").unwrap();
+        }
+
+        IRKind::DumpStack => {
+            writeln!(output, "    call sbl_dump_stack").unwrap();
+        }
+
+        IRKind::ExitWithCode => {
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    mov rdi, rax").unwrap();
+            writeln!(output, "    and rsp, -16").unwrap();
+            writeln!(output, "    call exit").unwrap();
+        }
+
+        IRKind::PrintStr => {
+            writeln!(output, "    POP_RBX").unwrap();
+            writeln!(output, "    POP_RAX").unwrap();
+            writeln!(output, "    call sbl_print_str").unwrap();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,556 @@
+use crate::{
+    common::{Error, SourceLocation},
+    ir::{IRKind, IR},
+    ir_execution::Value,
+    types::Type,
+};
+
+const MAGIC: &[u8; 4] = b"SBLB";
+const VERSION: u8 = 3;
+const DEBUG_SECTION_TAG: u8 = 1;
+
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            break;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_type(bytes: &mut Vec<u8>, typ: &Type) {
+    match typ {
+        Type::Type => bytes.push(0),
+        Type::Integer => bytes.push(1),
+        Type::Bool => bytes.push(2),
+        Type::Float => bytes.push(4),
+        Type::Procedure {
+            parameters,
+            return_types,
+        } => {
+            bytes.push(3);
+            write_unsigned(bytes, parameters.len() as u64);
+            for parameter in parameters {
+                write_type(bytes, parameter);
+            }
+            write_unsigned(bytes, return_types.len() as u64);
+            for return_type in return_types {
+                write_type(bytes, return_type);
+            }
+        }
+    }
+}
+
+fn write_value(bytes: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Integer(value) => {
+            bytes.push(0);
+            write_signed(bytes, *value);
+        }
+        Value::Float(value) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        Value::Bool(value) => {
+            bytes.push(2);
+            bytes.push(*value as u8);
+        }
+        Value::Procedure(id) => {
+            bytes.push(3);
+            write_unsigned(bytes, *id as u64);
+        }
+        Value::Type(typ) => {
+            bytes.push(4);
+            write_type(bytes, typ);
+        }
+    }
+}
+
+/// Encodes `procedures` and the constant pool their `PushConst`s refer to as bytecode,
+/// source locations are not stored
+pub fn serialize(procedures: &[Vec<IR>], constants: &[Value]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+
+    write_unsigned(&mut bytes, constants.len() as u64);
+    for constant in constants {
+        write_value(&mut bytes, constant);
+    }
+
+    write_unsigned(&mut bytes, procedures.len() as u64);
+    for procedure in procedures {
+        write_unsigned(&mut bytes, procedure.len() as u64);
+        for ir in procedure {
+            match &ir.kind {
+                IRKind::Exit => bytes.push(0),
+
+                IRKind::PushProc { id } => {
+                    bytes.push(1);
+                    write_unsigned(&mut bytes, *id as u64);
+                }
+                IRKind::PushInt { value } => {
+                    bytes.push(2);
+                    write_signed(&mut bytes, *value);
+                }
+                IRKind::PushFloat { value } => {
+                    bytes.push(36);
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                IRKind::PushBool { value } => {
+                    bytes.push(3);
+                    bytes.push(*value as u8);
+                }
+                IRKind::PushType { typ } => {
+                    bytes.push(4);
+                    write_type(&mut bytes, typ);
+                }
+                IRKind::PushMemory { offset, size } => {
+                    bytes.push(5);
+                    write_unsigned(&mut bytes, *offset as u64);
+                    write_unsigned(&mut bytes, *size as u64);
+                }
+                IRKind::PushConst { index } => {
+                    bytes.push(49);
+                    write_unsigned(&mut bytes, *index as u64);
+                }
+
+                IRKind::Add => bytes.push(6),
+                IRKind::Sub => bytes.push(7),
+                IRKind::Mul => bytes.push(8),
+                IRKind::Div => bytes.push(9),
+                IRKind::Mod => bytes.push(10),
+                IRKind::BitAnd => bytes.push(11),
+                IRKind::BitOr => bytes.push(12),
+                IRKind::BitXor => bytes.push(13),
+                IRKind::ShiftLeft => bytes.push(14),
+                IRKind::ShiftRight => bytes.push(15),
+                IRKind::LessThan => bytes.push(16),
+                IRKind::GreaterThan => bytes.push(17),
+                IRKind::LessThanEqual => bytes.push(18),
+                IRKind::GreaterThanEqual => bytes.push(19),
+                IRKind::Equal => bytes.push(20),
+                IRKind::NotEqual => bytes.push(21),
+                IRKind::Not => bytes.push(22),
+
+                IRKind::Dup => bytes.push(23),
+                IRKind::Drop => bytes.push(24),
+                IRKind::Swap => bytes.push(25),
+                IRKind::Over => bytes.push(26),
+                IRKind::Rot => bytes.push(27),
+
+                IRKind::Load => bytes.push(28),
+                IRKind::Store => bytes.push(29),
+
+                IRKind::Jump { relative_position } => {
+                    bytes.push(30);
+                    write_signed(&mut bytes, *relative_position as i64);
+                }
+                IRKind::JumpFalse { relative_position } => {
+                    bytes.push(31);
+                    write_signed(&mut bytes, *relative_position as i64);
+                }
+
+                IRKind::Call => bytes.push(32),
+                IRKind::TailCall => bytes.push(33),
+                IRKind::Return => bytes.push(34),
+
+                IRKind::Print => bytes.push(35),
+                IRKind::PrintStr => bytes.push(37),
+                IRKind::TwoDup => bytes.push(38),
+                IRKind::Nip => bytes.push(39),
+                IRKind::Tuck => bytes.push(40),
+                IRKind::Neg => bytes.push(41),
+                IRKind::And => bytes.push(42),
+                IRKind::Or => bytes.push(43),
+                IRKind::Max => bytes.push(51),
+                IRKind::Min => bytes.push(52),
+                IRKind::JumpTrue { relative_position } => {
+                    bytes.push(53);
+                    write_signed(&mut bytes, *relative_position as i64);
+                }
+                IRKind::PrintHex => bytes.push(54),
+                IRKind::PrintBin => bytes.push(55),
+                IRKind::Pick { depth } => {
+                    bytes.push(56);
+                    write_unsigned(&mut bytes, *depth as u64);
+                }
+                IRKind::Roll { depth } => {
+                    bytes.push(57);
+                    write_unsigned(&mut bytes, *depth as u64);
+                }
+                IRKind::DropN { count } => {
+                    bytes.push(44);
+                    write_unsigned(&mut bytes, *count as u64);
+                }
+                IRKind::DupN { count } => {
+                    bytes.push(45);
+                    write_unsigned(&mut bytes, *count as u64);
+                }
+                IRKind::Emit => bytes.push(46),
+                IRKind::StoreLocal { slot } => {
+                    bytes.push(47);
+                    write_unsigned(&mut bytes, *slot as u64);
+                }
+                IRKind::LoadLocal { slot } => {
+                    bytes.push(48);
+                    write_unsigned(&mut bytes, *slot as u64);
+                }
+                IRKind::Assert => bytes.push(50),
+                IRKind::ExitWithCode => bytes.push(58),
+                IRKind::DumpStack => bytes.push(59),
+            }
+        }
+    }
+
+    bytes
+}
+
+/// Encodes `procedures` like `serialize`, followed by a debug section holding every instruction's source location
+pub fn serialize_with_debug(procedures: &[Vec<IR>], constants: &[Value]) -> Vec<u8> {
+    let mut bytes = serialize(procedures, constants);
+    bytes.push(DEBUG_SECTION_TAG);
+
+    let mut filepaths: Vec<&str> = Vec::new();
+    for ir in procedures.iter().flatten() {
+        if !filepaths.contains(&(&ir.location.filepath as &str)) {
+            filepaths.push(&ir.location.filepath);
+        }
+    }
+    write_unsigned(&mut bytes, filepaths.len() as u64);
+    for filepath in &filepaths {
+        write_unsigned(&mut bytes, filepath.len() as u64);
+        bytes.extend_from_slice(filepath.as_bytes());
+    }
+
+    for ir in procedures.iter().flatten() {
+        let filepath_index = filepaths
+            .iter()
+            .position(|filepath| filepath == &ir.location.filepath)
+            .unwrap();
+        write_unsigned(&mut bytes, filepath_index as u64);
+        write_unsigned(&mut bytes, ir.location.position as u64);
+        write_unsigned(&mut bytes, ir.location.line as u64);
+        write_unsigned(&mut bytes, ir.location.column as u64);
+        write_unsigned(&mut bytes, ir.location.length as u64);
+    }
+
+    bytes
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn location(self: &Reader<'a>) -> SourceLocation {
+        SourceLocation {
+            filepath: "<bytecode>".to_string(),
+            position: self.position,
+            line: 1,
+            column: self.position + 1,
+            length: 0,
+        }
+    }
+
+    fn error(self: &Reader<'a>, message: &str) -> Error {
+        Error {
+            location: self.location(),
+            message: message.to_string(),
+        }
+    }
+
+    fn read_byte(self: &mut Reader<'a>) -> Result<u8, Error> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| self.error("Unexpected end of bytecode"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_unsigned(self: &mut Reader<'a>) -> Result<u64, Error> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift >= 64 {
+                return Err(self.error("Integer in bytecode is too large"));
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn read_usize(self: &mut Reader<'a>) -> Result<usize, Error> {
+        let value = self.read_unsigned()?;
+        usize::try_from(value).map_err(|_| self.error("Integer in bytecode is too large"))
+    }
+
+    fn read_signed(self: &mut Reader<'a>) -> Result<i64, Error> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift >= 64 {
+                return Err(self.error("Integer in bytecode is too large"));
+            }
+            value |= ((byte & 0x7F) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn read_isize(self: &mut Reader<'a>) -> Result<isize, Error> {
+        let value = self.read_signed()?;
+        isize::try_from(value).map_err(|_| self.error("Integer in bytecode is too large"))
+    }
+
+    fn read_f64(self: &mut Reader<'a>) -> Result<f64, Error> {
+        let mut bytes = [0; size_of::<f64>()];
+        for byte in &mut bytes {
+            *byte = self.read_byte()?;
+        }
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn read_string(self: &mut Reader<'a>) -> Result<String, Error> {
+        let length = self.read_usize()?;
+        let mut bytes = Vec::with_capacity(length);
+        for _ in 0..length {
+            bytes.push(self.read_byte()?);
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in bytecode string"))
+    }
+
+    fn read_value(self: &mut Reader<'a>) -> Result<Value, Error> {
+        Ok(match self.read_byte()? {
+            0 => Value::Integer(self.read_signed()?),
+            1 => Value::Float(self.read_f64()?),
+            2 => Value::Bool(self.read_byte()? != 0),
+            3 => Value::Procedure(self.read_usize()?),
+            4 => Value::Type(self.read_type()?),
+            _ => return Err(self.error("Unknown value tag")),
+        })
+    }
+
+    fn read_type(self: &mut Reader<'a>) -> Result<Type, Error> {
+        Ok(match self.read_byte()? {
+            0 => Type::Type,
+            1 => Type::Integer,
+            2 => Type::Bool,
+            3 => {
+                let mut parameters = Vec::new();
+                for _ in 0..self.read_usize()? {
+                    parameters.push(self.read_type()?);
+                }
+                let mut return_types = Vec::new();
+                for _ in 0..self.read_usize()? {
+                    return_types.push(self.read_type()?);
+                }
+                Type::Procedure {
+                    parameters,
+                    return_types,
+                }
+            }
+            4 => Type::Float,
+            _ => return Err(self.error("Unknown type tag")),
+        })
+    }
+}
+
+/// Decodes bytecode produced by `serialize` or `serialize_with_debug`.
+/// Without a debug section every instruction gets a location pointing at its byte offset.
+pub fn deserialize(bytes: &[u8]) -> Result<(Vec<Vec<IR>>, Vec<Value>), Error> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    for &expected in MAGIC {
+        if reader.read_byte()? != expected {
+            return Err(reader.error("Not a bytecode file"));
+        }
+    }
+    if reader.read_byte()? != VERSION {
+        return Err(reader.error("Unsupported bytecode version"));
+    }
+
+    let mut constants = Vec::new();
+    for _ in 0..reader.read_usize()? {
+        constants.push(reader.read_value()?);
+    }
+
+    let mut procedures = Vec::new();
+    for _ in 0..reader.read_usize()? {
+        let mut procedure = Vec::new();
+        for _ in 0..reader.read_usize()? {
+            let location = reader.location();
+            let kind = match reader.read_byte()? {
+                0 => IRKind::Exit,
+
+                1 => IRKind::PushProc {
+                    id: reader.read_usize()?,
+                },
+                2 => IRKind::PushInt {
+                    value: reader.read_signed()?,
+                },
+                3 => IRKind::PushBool {
+                    value: reader.read_byte()? != 0,
+                },
+                4 => IRKind::PushType {
+                    typ: reader.read_type()?,
+                },
+                5 => IRKind::PushMemory {
+                    offset: reader.read_usize()?,
+                    size: reader.read_usize()?,
+                },
+                49 => IRKind::PushConst {
+                    index: reader.read_usize()?,
+                },
+
+                6 => IRKind::Add,
+                7 => IRKind::Sub,
+                8 => IRKind::Mul,
+                9 => IRKind::Div,
+                10 => IRKind::Mod,
+                11 => IRKind::BitAnd,
+                12 => IRKind::BitOr,
+                13 => IRKind::BitXor,
+                14 => IRKind::ShiftLeft,
+                15 => IRKind::ShiftRight,
+                16 => IRKind::LessThan,
+                17 => IRKind::GreaterThan,
+                18 => IRKind::LessThanEqual,
+                19 => IRKind::GreaterThanEqual,
+                20 => IRKind::Equal,
+                21 => IRKind::NotEqual,
+                22 => IRKind::Not,
+
+                23 => IRKind::Dup,
+                24 => IRKind::Drop,
+                25 => IRKind::Swap,
+                26 => IRKind::Over,
+                27 => IRKind::Rot,
+
+                28 => IRKind::Load,
+                29 => IRKind::Store,
+
+                30 => IRKind::Jump {
+                    relative_position: reader.read_isize()?,
+                },
+                31 => IRKind::JumpFalse {
+                    relative_position: reader.read_isize()?,
+                },
+
+                32 => IRKind::Call,
+                33 => IRKind::TailCall,
+                34 => IRKind::Return,
+
+                35 => IRKind::Print,
+
+                36 => IRKind::PushFloat {
+                    value: reader.read_f64()?,
+                },
+                37 => IRKind::PrintStr,
+                38 => IRKind::TwoDup,
+                39 => IRKind::Nip,
+                40 => IRKind::Tuck,
+                41 => IRKind::Neg,
+                42 => IRKind::And,
+                43 => IRKind::Or,
+                51 => IRKind::Max,
+                52 => IRKind::Min,
+                53 => IRKind::JumpTrue {
+                    relative_position: reader.read_isize()?,
+                },
+                54 => IRKind::PrintHex,
+                55 => IRKind::PrintBin,
+                56 => IRKind::Pick {
+                    depth: reader.read_usize()?,
+                },
+                57 => IRKind::Roll {
+                    depth: reader.read_usize()?,
+                },
+                44 => IRKind::DropN {
+                    count: reader.read_usize()?,
+                },
+                45 => IRKind::DupN {
+                    count: reader.read_usize()?,
+                },
+                46 => IRKind::Emit,
+                47 => IRKind::StoreLocal {
+                    slot: reader.read_usize()?,
+                },
+                48 => IRKind::LoadLocal {
+                    slot: reader.read_usize()?,
+                },
+                50 => IRKind::Assert,
+                58 => IRKind::ExitWithCode,
+                59 => IRKind::DumpStack,
+
+                _ => {
+                    return Err(Error {
+                        location,
+                        message: "Unknown instruction tag".to_string(),
+                    })
+                }
+            };
+            procedure.push(IR { location, kind });
+        }
+        procedures.push(procedure);
+    }
+
+    if reader.position != bytes.len() {
+        if reader.read_byte()? != DEBUG_SECTION_TAG {
+            return Err(reader.error("Unexpected data after the end of the bytecode"));
+        }
+
+        let mut filepaths = Vec::new();
+        for _ in 0..reader.read_usize()? {
+            filepaths.push(reader.read_string()?);
+        }
+        for procedure in &mut procedures {
+            for ir in procedure {
+                let filepath_index = reader.read_usize()?;
+                ir.location = SourceLocation {
+                    filepath: filepaths
+                        .get(filepath_index)
+                        .ok_or_else(|| reader.error("Unknown filepath index"))?
+                        .clone(),
+                    position: reader.read_usize()?,
+                    line: reader.read_usize()?,
+                    column: reader.read_usize()?,
+                    length: reader.read_usize()?,
+                };
+            }
+        }
+
+        if reader.position != bytes.len() {
+            return Err(reader.error("Unexpected data after the end of the bytecode"));
+        }
+    }
+
+    Ok((procedures, constants))
+}
@@ -1,9 +1,13 @@
+use std::fmt::Display;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SourceLocation {
     pub filepath: String,
     pub position: usize,
     pub line: usize,
     pub column: usize,
+    /// How many bytes of source the location covers, such as the whole token, this is 0 when it is not known
+    pub length: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -11,3 +15,129 @@ pub struct Error {
     pub location: SourceLocation,
     pub message: String,
 }
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.location.filepath, self.location.line, self.location.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Extra information about the diagnostic before it
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Feedback from a pass that does not stop compilation by itself, unlike `Error`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_error(self: &Diagnostic) -> Error {
+        Error {
+            location: self.location.clone(),
+            message: self.message.clone(),
+        }
+    }
+}
+
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            location: error.location,
+            message: error.message,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.to_error())
+    }
+}
+
+/// Formats `error` followed by the source line it points at, with the offending text underlined
+pub fn render_error(source: &str, error: &Error) -> String {
+    render_error_with_color(source, error, false)
+}
+
+/// Formats `diagnostic` like `render_error_with_color`, prefixed with its severity
+pub fn render_diagnostic_with_color(source: &str, diagnostic: &Diagnostic, color: bool) -> String {
+    format!(
+        "{}: {}",
+        diagnostic.severity,
+        render_error_with_color(source, &diagnostic.to_error(), color)
+    )
+}
+
+/// Same as `render_error`, but with ANSI colors when `color` is true,
+/// the message is red and the underline is cyan
+pub fn render_error_with_color(source: &str, error: &Error, color: bool) -> String {
+    // positions are byte offsets, so step back to the start of the character if it points into one
+    let mut position = error.location.position.min(source.len());
+    while !source.is_char_boundary(position) {
+        position -= 1;
+    }
+
+    let line_start = source[..position].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[position..]
+        .find('\n')
+        .map_or(source.len(), |index| position + index);
+    let line = &source[line_start..line_end];
+
+    // copy tabs from the line so the caret lines up however wide the terminal draws them
+    let indent: String = source[line_start..position]
+        .chars()
+        .map(|chr| if chr == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    // only the part of the location on the first line is underlined
+    let length = source[position..line_end]
+        .char_indices()
+        .take_while(|&(offset, _)| offset < error.location.length)
+        .count()
+        .max(1);
+
+    let (red, cyan, reset) = if color {
+        ("\x1b[31m", "\x1b[36m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+    format!(
+        "{}:{}:{}: {}{}{}\n{}\n{}{}^{}{}",
+        error.location.filepath,
+        error.location.line,
+        error.location.column,
+        red,
+        error.message,
+        reset,
+        line.trim_end_matches('\r'),
+        indent,
+        cyan,
+        "~".repeat(length - 1),
+        reset
+    )
+}
@@ -1,68 +1,720 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
 use crate::{
-    common::Error,
+    common::{Error, SourceLocation},
     ir::{IRKind, IR},
-    token::TokenKind,
-    tokenizer::Tokenizer,
+    ir_execution::{run_procedure, RunOptions, Value},
+    ir_type_checking::get_stack_at_end,
+    lexer::Lexer,
+    token::{Token, TokenKind},
+    tokenizer::{TokenArray, Tokenizer},
+    types::{format_types, Type},
 };
 
 #[derive(Debug, Clone, PartialEq)]
+/// The `location` of a scope is where the construct that opened it starts
 enum Scope {
     While {
         position: usize,
+        location: SourceLocation,
     },
     WhileBody {
         while_position: usize,
         conditional_jump_position: usize,
+        location: SourceLocation,
     },
     If {
         conditional_jump_position: usize,
+        end_jump_positions: Vec<usize>,
+        location: SourceLocation,
+    },
+    Elif {
+        end_jump_positions: Vec<usize>,
+        location: SourceLocation,
     },
     Else {
-        end_of_then_jump_position: usize,
+        end_jump_positions: Vec<usize>,
+        location: SourceLocation,
     },
     Proc {
         id: usize,
+        location: SourceLocation,
     },
     Const {
+        name: String,
+        start_position: usize,
+        location: SourceLocation,
+    },
+    Alias {
+        name: String,
+        start_position: usize,
+        location: SourceLocation,
+    },
+    Block {
+        location: SourceLocation,
+    },
+    ConstantEval {
         start_position: usize,
     },
-    Block,
-    ConstantEval,
     Global,
 }
 
+/// Makes the error for a scope that is still open when there are no tokens left to close it
+fn unclosed_scope_error(scope: Scope) -> Error {
+    let (location, message) = match scope {
+        Scope::While { location, .. } => {
+            (location, "Expected '{' after the condition of this 'while'")
+        }
+        Scope::Elif { location, .. } => {
+            (location, "Expected '{' after the condition of this 'elif'")
+        }
+        Scope::WhileBody { location, .. } => (location, "This 'while' is never closed with a '}'"),
+        Scope::If { location, .. } => (location, "This 'if' is never closed with a '}'"),
+        Scope::Else { location, .. } => (location, "This 'else' is never closed with a '}'"),
+        Scope::Proc { location, .. } => (location, "This 'proc' is never closed with a '}'"),
+        Scope::Const { location, .. } => (location, "This 'const' is never closed with a '}'"),
+        Scope::Alias { location, .. } => (location, "This 'alias' is never closed with a '}'"),
+        Scope::Block { location } => (location, "This '{' is never closed with a '}'"),
+        Scope::ConstantEval { .. } | Scope::Global => unreachable!(),
+    };
+    Error {
+        location,
+        message: message.to_string(),
+    }
+}
+
+/// A procedure that was declared with a name
 #[derive(Debug, Clone, PartialEq)]
-enum Decl {
+pub struct ProcDeclaration {
+    pub name: String,
+    pub id: usize,
+    pub location: SourceLocation,
+}
+
+/// A declaration whose name was already declared in the same or an enclosing scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowedDeclaration {
+    pub name: String,
+    pub location: SourceLocation,
+    pub shadowed_location: SourceLocation,
+}
+
+/// What a name in the global scope was declared as
+#[derive(Debug, Clone, PartialEq)]
+pub enum GlobalDeclarationKind {
     Proc { id: usize },
-    Const { ir: Vec<IR> },
+    Const,
+    Alias,
+    Memory { offset: usize, size: usize },
+    Local { slot: usize },
 }
 
-pub fn compile_ir(
-    tokenizer: &mut dyn Tokenizer,
+/// A name declared in the global scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalDeclaration {
+    pub name: String,
+    pub kind: GlobalDeclarationKind,
+    pub location: SourceLocation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Decl {
+    /// A procedure that can already be referred to, but whose definition has not been reached yet
+    DeclaredProc {
+        id: usize,
+    },
+    Proc {
+        id: usize,
+    },
+    Const {
+        ir: Vec<IR>,
+    },
+    /// The body of an alias, its locals start at slot 0 and are moved after the locals in use where it is expanded
+    Alias {
+        ir: Vec<IR>,
+    },
+    Memory {
+        offset: usize,
+        size: usize,
+    },
+    Local {
+        slot: usize,
+    },
+}
+
+/// The declarations of a scope, with the location each one was declared at
+type Decls = Vec<(String, Decl, SourceLocation)>;
+
+/// How many instructions code run while compiling can take, so a loop that never ends is an error instead of a hang
+const CONSTANT_EVALUATION_FUEL: u64 = 10_000_000;
+
+/// The output of code run while compiling, which is not allowed to print
+struct ConstantOutput;
+
+impl Write for ConstantOutput {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("code run while compiling can not print"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn evaluate_constant(
+    procedures: &[Vec<IR>],
+    mut ir: Vec<IR>,
+    exit_location: SourceLocation,
+) -> Result<Vec<Value>, Error> {
+    ir.push(IR {
+        location: exit_location,
+        kind: IRKind::Exit,
+    });
+    let mut procedures = procedures.to_vec();
+    procedures.push(ir);
+    let mut out = ConstantOutput;
+    let mut options = RunOptions::new(&mut out);
+    options.fuel = Some(CONSTANT_EVALUATION_FUEL);
+    run_procedure(&procedures, procedures.len() - 1, &mut options).map_err(|error| {
+        if options.fuel == Some(0) {
+            Error {
+                location: error.location,
+                message: format!(
+                    "Code run while compiling took more than {} instructions",
+                    CONSTANT_EVALUATION_FUEL
+                ),
+            }
+        } else {
+            error
+        }
+    })
+}
+
+/// Gets the position in the current procedure where the code of the innermost scopes starts,
+/// instructions before it can not be changed because a scope still refers to the position after them
+fn get_code_start(scopes: &[(Decls, Scope)]) -> usize {
+    let mut start = 0;
+    for (_, scope) in scopes.iter().rev() {
+        match scope {
+            Scope::While { position, .. } => start = start.max(*position),
+            Scope::Const { start_position, .. } | Scope::Alias { start_position, .. } => {
+                start = start.max(*start_position)
+            }
+            Scope::ConstantEval { .. } => return usize::MAX,
+            Scope::Proc { .. } | Scope::Global => break,
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Checks if the last instruction of `procedure` can be replaced, it can not when it is before the code start
+/// or a jump lands on it or right after it, because the replacement would then be skipped or run without it
+fn can_replace_last(procedure: &[IR], scopes: &[(Decls, Scope)]) -> bool {
+    let len = procedure.len();
+    len > get_code_start(scopes)
+        && procedure
+            .iter()
+            .enumerate()
+            .all(|(position, ir)| match ir.kind {
+                IRKind::Jump { relative_position }
+                | IRKind::JumpFalse { relative_position }
+                | IRKind::JumpTrue { relative_position } => {
+                    let target = position as isize + relative_position;
+                    target != len as isize - 1 && target != len as isize
+                }
+                _ => true,
+            })
+}
+
+/// Pushes the jump taken when the condition of an `if`, `elif` or `while` is false, to be patched later,
+/// and returns its position. A `!` directly before it is folded into a `JumpTrue` instead of being run every time.
+fn push_conditional_jump(
+    procedure: &mut Vec<IR>,
+    scopes: &[(Decls, Scope)],
+    location: SourceLocation,
+) -> usize {
+    let kind = if procedure.last().map(|ir| &ir.kind) == Some(&IRKind::Not)
+        && can_replace_last(procedure, scopes)
+    {
+        procedure.pop();
+        IRKind::JumpTrue {
+            relative_position: 0,
+        }
+    } else {
+        IRKind::JumpFalse {
+            relative_position: 0,
+        }
+    };
+    procedure.push(IR { location, kind });
+    procedure.len() - 1
+}
+
+/// Points the `Jump`s at the end of each branch of an if chain to the current end of `procedure`
+fn patch_end_jumps(procedure: &mut [IR], end_jump_positions: &[usize]) {
+    let current_pos = procedure.len();
+    for &jump_position in end_jump_positions {
+        if let IRKind::Jump { relative_position } = &mut procedure[jump_position].kind {
+            *relative_position = current_pos as isize - jump_position as isize;
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+/// Reserves an id for every named procedure declared directly in the block starting at the next token,
+/// so procedures in the same block can refer to each other no matter which one is defined first
+fn declare_procedures(
+    tokenizer: &dyn Tokenizer,
+    scopes: &mut [(Decls, Scope)],
     procedures: &mut Vec<Vec<IR>>,
+    signatures: &mut Vec<Type>,
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
 ) -> Result<(), Error> {
-    let mut scopes: Vec<(Vec<(String, Decl)>, Scope)> = Vec::new();
-    scopes.push((Vec::new(), Scope::Global));
-    procedures.push(Vec::new());
+    let mut depth = 0;
+    let mut offset = 0;
+    loop {
+        let token = tokenizer.peek_nth(offset)?;
+        match token.kind {
+            TokenKind::EndOfFile => break,
 
-    'main_loop: loop {
-        let mut current_procedure = None;
-        for (_, scope) in scopes.iter().rev() {
-            match scope {
-                Scope::Proc { id } => {
-                    current_procedure = Some(*id);
+            TokenKind::OpenBrace | TokenKind::OpenParenthesis => depth += 1,
+
+            TokenKind::CloseBrace | TokenKind::CloseParenthesis => {
+                if depth == 0 {
                     break;
                 }
+                depth -= 1;
+            }
 
-                Scope::Global => {
-                    current_procedure = Some(0);
-                    break;
+            TokenKind::Proc if depth == 0 => {
+                let name_token = tokenizer.peek_nth(offset + 1)?;
+                if name_token.kind == TokenKind::Name {
+                    let id = procedures.len();
+                    procedures.push(Vec::new());
+                    // replaced with the real signature once the definition is reached
+                    signatures.push(Type::Procedure {
+                        parameters: Vec::new(),
+                        return_types: Vec::new(),
+                    });
+                    declare(
+                        scopes,
+                        shadowed_declarations,
+                        name_token.data.get_string(),
+                        Decl::DeclaredProc { id },
+                        name_token.location,
+                    )?;
+                }
+            }
+
+            _ => {}
+        }
+        offset += 1;
+    }
+    Ok(())
+}
+
+/// Gets the type that a built in type name like `int` refers to
+fn get_builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "type" => Some(Type::Type),
+        "int" => Some(Type::Integer),
+        "float" => Some(Type::Float),
+        "bool" => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+/// Checks a `#( ... -- ... )` comment against the signature of the procedure after it.
+/// There has to be a name for each parameter and return type, names of built in types also have to match the type.
+fn check_stack_effect(
+    stack_effect: &Token,
+    parameters: &[Type],
+    return_types: &[Type],
+) -> Result<(), Error> {
+    let text = stack_effect.data.get_string();
+    let names: Vec<&str> = text.split_whitespace().collect();
+    let separator = names
+        .iter()
+        .position(|&name| name == "--")
+        .ok_or_else(|| Error {
+            location: stack_effect.location.clone(),
+            message: "Expected '--' in the stack effect comment".to_string(),
+        })?;
+
+    let matches = |names: &[&str], types: &[Type]| {
+        names.len() == types.len()
+            && names
+                .iter()
+                .zip(types)
+                .all(|(name, typ)| get_builtin_type(name).is_none_or(|named| &named == typ))
+    };
+    if !matches(&names[..separator], parameters) || !matches(&names[separator + 1..], return_types)
+    {
+        return Err(Error {
+            location: stack_effect.location.clone(),
+            message: format!(
+                "Stack effect '{}' does not match the signature ({}) -> ({})",
+                names.join(" "),
+                format_types(parameters),
+                format_types(return_types),
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Errors if `name` is a built in type name, which a declaration would hide because names are looked up first
+fn check_not_builtin_type(name: &str, location: &SourceLocation) -> Result<(), Error> {
+    if get_builtin_type(name).is_some() {
+        return Err(Error {
+            location: location.clone(),
+            message: format!("Cannot redefine built-in type name '{}'", name),
+        });
+    }
+    Ok(())
+}
+
+/// Adds a declaration to the innermost scope, remembering the nearest declaration with the same name that it shadows
+fn declare(
+    scopes: &mut [(Decls, Scope)],
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
+    name: String,
+    decl: Decl,
+    location: SourceLocation,
+) -> Result<(), Error> {
+    check_not_builtin_type(&name, &location)?;
+    let shadowed = scopes.iter().rev().find_map(|(decls, _)| {
+        decls
+            .iter()
+            .rev()
+            .find(|(decl_name, _, _)| decl_name == &name)
+    });
+    if let Some((_, _, shadowed_location)) = shadowed {
+        shadowed_declarations.push(ShadowedDeclaration {
+            name: name.clone(),
+            location: location.clone(),
+            shadowed_location: shadowed_location.clone(),
+        });
+    }
+    scopes.last_mut().unwrap().0.push((name, decl, location));
+    Ok(())
+}
+
+/// Gets where the code that `typeof` looks at starts, and the types on the stack there.
+/// Code evaluated at compile time starts with an empty stack, otherwise it is the start of the procedure.
+fn get_stack_analysis_start(
+    scopes: &[(Decls, Scope)],
+    signatures: &[Type],
+    current_procedure: usize,
+) -> (usize, Vec<Type>) {
+    for (_, scope) in scopes.iter().rev() {
+        match scope {
+            Scope::Const { start_position, .. } | Scope::ConstantEval { start_position } => {
+                return (*start_position, Vec::new())
+            }
+            Scope::Proc { .. } | Scope::Global => break,
+            _ => {}
+        }
+    }
+    match &signatures[current_procedure] {
+        Type::Procedure { parameters, .. } => (0, parameters.clone()),
+        _ => unreachable!(),
+    }
+}
+
+fn get_current_procedure(scopes: &[(Decls, Scope)]) -> usize {
+    for (_, scope) in scopes.iter().rev() {
+        match scope {
+            Scope::Proc { id, .. } => return *id,
+            Scope::Global => return 0,
+            _ => {}
+        };
+    }
+    unreachable!()
+}
+
+/// Gets the first local slot that is not used by any local visible in the current procedure,
+/// the slots of a scope's locals are free to be reused once the scope has closed
+fn get_next_local_slot(scopes: &[(Decls, Scope)]) -> usize {
+    let mut slot = 0;
+    for (decls, scope) in scopes.iter().rev() {
+        slot += decls
+            .iter()
+            .filter(|(_, decl, _)| matches!(decl, Decl::Local { .. }))
+            .count();
+        if let Scope::Proc { .. } | Scope::Alias { .. } | Scope::Global = scope {
+            break;
+        }
+    }
+    slot
+}
+
+/// Compiles the tokens inside a pair of parentheses and evaluates them at compile time,
+/// every resulting value must be a type
+fn evaluate_types(
+    tokenizer: &mut dyn Tokenizer,
+    scopes: &mut Vec<(Decls, Scope)>,
+    procedures: &mut Vec<Vec<IR>>,
+    signatures: &mut Vec<Type>,
+    proc_declarations: &mut Vec<ProcDeclaration>,
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
+    memory_size: &mut usize,
+) -> Result<Vec<Type>, Error> {
+    let open_parenthesis = tokenizer.expect_token(TokenKind::OpenParenthesis)?;
+
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut depth = 1;
+    let close_parenthesis = loop {
+        let token = tokenizer.next_token()?;
+        match token.kind {
+            TokenKind::OpenParenthesis => depth += 1,
+
+            TokenKind::CloseParenthesis => {
+                depth -= 1;
+                if depth == 0 {
+                    break token;
                 }
+            }
+
+            TokenKind::EndOfFile => {
+                return Err(Error {
+                    location: open_parenthesis.location,
+                    message: "Unclosed '('".to_string(),
+                })
+            }
 
-                _ => {}
-            };
+            _ => {}
         }
-        let current_procedure = current_procedure.unwrap();
+        tokens.push(token);
+    };
+
+    let current_procedure = get_current_procedure(scopes);
+    let start_position = procedures[current_procedure].len();
+    scopes.push((Vec::new(), Scope::ConstantEval { start_position }));
+    let mut token_array = TokenArray::new(open_parenthesis.location.filepath.clone(), tokens);
+    declare_procedures(
+        &token_array,
+        scopes,
+        procedures,
+        signatures,
+        shadowed_declarations,
+    )?;
+    compile_tokens(
+        &mut token_array,
+        scopes,
+        procedures,
+        signatures,
+        proc_declarations,
+        shadowed_declarations,
+        memory_size,
+    )?;
+    let scope = scopes.pop().unwrap().1;
+    if !matches!(scope, Scope::ConstantEval { .. }) {
+        return Err(unclosed_scope_error(scope));
+    }
+
+    let ir = procedures[current_procedure].split_off(start_position);
+    evaluate_constant(procedures, ir, close_parenthesis.location)?
+        .into_iter()
+        .map(|value| match value {
+            Value::Type(typ) => Ok(typ),
+            value => Err(Error {
+                location: open_parenthesis.location.clone(),
+                message: format!("Expected a type, but got '{}'", value),
+            }),
+        })
+        .collect()
+}
+
+/// Reads every token up to the end of the file into `tokens`, splicing in the tokens of `include`d files,
+/// `including` holds the files currently being read so cyclic includes can be reported
+fn collect_tokens(
+    tokenizer: &mut dyn Tokenizer,
+    tokens: &mut Vec<Token>,
+    including: &mut Vec<PathBuf>,
+) -> Result<Token, Error> {
+    loop {
+        let token = tokenizer.next_token()?;
+        match token.kind {
+            TokenKind::EndOfFile => return Ok(token),
+
+            TokenKind::Include => {
+                let path_token = tokenizer.expect_token(TokenKind::String)?;
+                let path = Path::new(&token.location.filepath)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(path_token.data.get_string());
+                let unable_to_open = || Error {
+                    location: path_token.location.clone(),
+                    message: format!("Unable to open file '{}'", path.display()),
+                };
+                let canonical_path = path.canonicalize().map_err(|_| unable_to_open())?;
+                if including.contains(&canonical_path) {
+                    return Err(Error {
+                        location: path_token.location.clone(),
+                        message: format!("Cyclic include of '{}'", path.display()),
+                    });
+                }
+                let source = std::fs::read_to_string(&path).map_err(|_| unable_to_open())?;
+
+                including.push(canonical_path);
+                let mut lexer = Lexer::new(path.display().to_string(), &source);
+                collect_tokens(&mut lexer, tokens, including)?;
+                including.pop();
+            }
+
+            _ => tokens.push(token),
+        }
+    }
+}
+
+pub fn compile_ir(
+    tokenizer: &mut dyn Tokenizer,
+    procedures: &mut Vec<Vec<IR>>,
+    signatures: &mut Vec<Type>,
+    proc_declarations: &mut Vec<ProcDeclaration>,
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
+    global_declarations: &mut Vec<GlobalDeclaration>,
+) -> Result<(), Error> {
+    let mut state = CompileState::new();
+    compile_more(
+        tokenizer,
+        &mut state,
+        procedures,
+        signatures,
+        proc_declarations,
+        shadowed_declarations,
+    )?;
+    global_declarations.extend(state.get_global_declarations());
+    Ok(())
+}
+
+/// The global scope kept between calls to `compile_more`, so later code can refer to earlier declarations
+#[derive(Debug, Clone)]
+pub struct CompileState {
+    scopes: Vec<(Decls, Scope)>,
+    memory_size: usize,
+}
+
+impl CompileState {
+    pub fn new() -> CompileState {
+        CompileState {
+            scopes: vec![(Vec::new(), Scope::Global)],
+            memory_size: 0,
+        }
+    }
+
+    pub fn get_global_declarations(self: &CompileState) -> Vec<GlobalDeclaration> {
+        self.scopes[0]
+            .0
+            .iter()
+            .map(|(name, decl, location)| {
+                let kind = match decl {
+                    Decl::DeclaredProc { id } | Decl::Proc { id } => {
+                        GlobalDeclarationKind::Proc { id: *id }
+                    }
+                    Decl::Const { .. } => GlobalDeclarationKind::Const,
+                    Decl::Alias { .. } => GlobalDeclarationKind::Alias,
+                    Decl::Memory { offset, size } => GlobalDeclarationKind::Memory {
+                        offset: *offset,
+                        size: *size,
+                    },
+                    Decl::Local { slot } => GlobalDeclarationKind::Local { slot: *slot },
+                };
+                GlobalDeclaration {
+                    name: name.clone(),
+                    kind,
+                    location: location.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for CompileState {
+    fn default() -> CompileState {
+        CompileState::new()
+    }
+}
+
+/// Compiles more global code on the end of procedure 0, moving its `Exit` after the new code.
+/// On an error `procedures`, `signatures` and `state` may be left half updated
+pub fn compile_more(
+    tokenizer: &mut dyn Tokenizer,
+    state: &mut CompileState,
+    procedures: &mut Vec<Vec<IR>>,
+    signatures: &mut Vec<Type>,
+    proc_declarations: &mut Vec<ProcDeclaration>,
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
+) -> Result<(), Error> {
+    // the whole file is tokenized up front so declarations can be looked ahead for cheaply
+    let mut tokens = Vec::new();
+    let mut including = Vec::new();
+    if let Ok(path) = Path::new(&tokenizer.peek_token()?.location.filepath).canonicalize() {
+        including.push(path);
+    }
+    let end_of_file = collect_tokens(tokenizer, &mut tokens, &mut including)?;
+    let mut token_array = TokenArray::new(end_of_file.location.filepath.clone(), tokens);
+
+    if procedures.is_empty() {
+        procedures.push(Vec::new());
+        signatures.push(Type::Procedure {
+            parameters: Vec::new(),
+            return_types: Vec::new(),
+        });
+    } else if let Some(IR {
+        kind: IRKind::Exit, ..
+    }) = procedures[0].last()
+    {
+        procedures[0].pop();
+    }
+
+    declare_procedures(
+        &token_array,
+        &mut state.scopes,
+        procedures,
+        signatures,
+        shadowed_declarations,
+    )?;
+    compile_tokens(
+        &mut token_array,
+        &mut state.scopes,
+        procedures,
+        signatures,
+        proc_declarations,
+        shadowed_declarations,
+        &mut state.memory_size,
+    )?;
+
+    assert_eq!(token_array.peek_kind()?, TokenKind::EndOfFile);
+    if state.scopes.len() > 1 {
+        return Err(unclosed_scope_error(state.scopes.pop().unwrap().1));
+    }
+    procedures[0].push(IR {
+        location: end_of_file.location,
+        kind: IRKind::Exit,
+    });
+
+    Ok(())
+}
+
+fn compile_tokens(
+    tokenizer: &mut dyn Tokenizer,
+    scopes: &mut Vec<(Decls, Scope)>,
+    procedures: &mut Vec<Vec<IR>>,
+    signatures: &mut Vec<Type>,
+    proc_declarations: &mut Vec<ProcDeclaration>,
+    shadowed_declarations: &mut Vec<ShadowedDeclaration>,
+    memory_size: &mut usize,
+) -> Result<(), Error> {
+    // the stack effect comment directly before the `proc` being compiled, if there was one
+    let mut stack_effect: Option<Token> = None;
+    'main_loop: loop {
+        let current_procedure = get_current_procedure(scopes);
 
         let token = tokenizer.next_token()?;
         match token.kind {
@@ -76,22 +728,166 @@ pub fn compile_ir(
                 });
             }
 
+            TokenKind::Float => {
+                let value = token.data.get_float();
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: IRKind::PushFloat { value },
+                });
+            }
+
+            TokenKind::String => {
+                // the bytes are stored into a reserved memory region every time the literal runs
+                let bytes = token.data.get_string().into_bytes();
+                let offset = *memory_size;
+                let size = bytes.len().div_ceil(size_of::<i64>()) * size_of::<i64>();
+                *memory_size += size;
+
+                for (index, chunk) in bytes.chunks(size_of::<i64>()).enumerate() {
+                    let mut value = [0; size_of::<i64>()];
+                    value[..chunk.len()].copy_from_slice(chunk);
+                    procedures[current_procedure].push(IR {
+                        location: token.location.clone(),
+                        kind: IRKind::PushMemory {
+                            offset: offset + index * size_of::<i64>(),
+                            size: size_of::<i64>(),
+                        },
+                    });
+                    procedures[current_procedure].push(IR {
+                        location: token.location.clone(),
+                        kind: IRKind::PushInt {
+                            value: i64::from_le_bytes(value),
+                        },
+                    });
+                    procedures[current_procedure].push(IR {
+                        location: token.location.clone(),
+                        kind: IRKind::Store,
+                    });
+                }
+
+                procedures[current_procedure].push(IR {
+                    location: token.location.clone(),
+                    kind: IRKind::PushMemory { offset, size },
+                });
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: IRKind::PushInt {
+                        value: bytes.len() as i64,
+                    },
+                });
+            }
+
+            TokenKind::True => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::PushBool { value: true },
+            }),
+
+            TokenKind::False => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::PushBool { value: false },
+            }),
+
             TokenKind::Name => {
                 let name = token.data.get_string();
-                for (decls, _) in scopes.iter().rev() {
-                    for (decl_name, decl) in decls.iter().rev() {
-                        if decl_name == &name {
-                            match decl {
-                                Decl::Proc { id } => procedures[current_procedure].push(IR {
+                // an alias is only declared once its body is closed, so it could never be expanded inside of itself
+                if scopes.iter().any(
+                    |(_, scope)| matches!(scope, Scope::Alias { name: alias_name, .. } if alias_name == &name),
+                ) {
+                    return Err(Error {
+                        location: token.location,
+                        message: format!("Alias '{}' can not refer to itself", name),
+                    });
+                }
+                // locals from outside of the procedure or compile time code being compiled can not be reached
+                let mut outside_frame = false;
+                for (decls, scope) in scopes.iter().rev() {
+                    // something already defined in this scope wins over a procedure that is only declared,
+                    // otherwise the first of the declared procedures is the next one to be defined
+                    let found = decls
+                        .iter()
+                        .rev()
+                        .find(|(decl_name, decl, _)| {
+                            decl_name == &name && !matches!(decl, Decl::DeclaredProc { .. })
+                        })
+                        .or_else(|| decls.iter().find(|(decl_name, _, _)| decl_name == &name));
+                    if let Some((_, decl, _)) = found {
+                        match decl {
+                            Decl::DeclaredProc { id } | Decl::Proc { id } => {
+                                procedures[current_procedure].push(IR {
                                     location: token.location.clone(),
                                     kind: IRKind::PushProc { id: *id },
-                                }),
-                                Decl::Const { ir: _ } => todo!(),
+                                })
+                            }
+                            Decl::Memory { offset, size } => {
+                                procedures[current_procedure].push(IR {
+                                    location: token.location.clone(),
+                                    kind: IRKind::PushMemory {
+                                        offset: *offset,
+                                        size: *size,
+                                    },
+                                })
+                            }
+                            Decl::Const { ir } => {
+                                for ir in ir {
+                                    procedures[current_procedure].push(IR {
+                                        location: token.location.clone(),
+                                        kind: ir.kind.clone(),
+                                    });
+                                }
+                            }
+                            Decl::Alias { ir } => {
+                                let first_slot = get_next_local_slot(scopes);
+                                for ir in ir {
+                                    let kind = match ir.kind {
+                                        IRKind::StoreLocal { slot } => IRKind::StoreLocal {
+                                            slot: first_slot + slot,
+                                        },
+                                        IRKind::LoadLocal { slot } => IRKind::LoadLocal {
+                                            slot: first_slot + slot,
+                                        },
+                                        ref kind => kind.clone(),
+                                    };
+                                    procedures[current_procedure].push(IR {
+                                        location: ir.location.clone(),
+                                        kind,
+                                    });
+                                }
+                            }
+                            Decl::Local { slot } => {
+                                if outside_frame {
+                                    return Err(Error {
+                                        location: token.location,
+                                        message: format!(
+                                            "Local '{}' can not be used outside of the procedure it belongs to",
+                                            name
+                                        ),
+                                    });
+                                }
+                                procedures[current_procedure].push(IR {
+                                    location: token.location.clone(),
+                                    kind: IRKind::LoadLocal { slot: *slot },
+                                })
                             }
-                            continue 'main_loop;
                         }
+                        continue 'main_loop;
+                    }
+                    if let Scope::Proc { .. }
+                    | Scope::Const { .. }
+                    | Scope::Alias { .. }
+                    | Scope::ConstantEval { .. } = scope
+                    {
+                        outside_frame = true;
                     }
                 }
+
+                let typ = get_builtin_type(&name).ok_or_else(|| Error {
+                    location: token.location.clone(),
+                    message: format!("Unable to find name '{}'", name),
+                })?;
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: IRKind::PushType { typ },
+                });
             }
 
             TokenKind::Print => procedures[current_procedure].push(IR {
@@ -99,20 +895,89 @@ pub fn compile_ir(
                 kind: IRKind::Print,
             }),
 
+            TokenKind::PrintStr => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::PrintStr,
+            }),
+
+            TokenKind::PrintHex => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::PrintHex,
+            }),
+
+            TokenKind::PrintBin => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::PrintBin,
+            }),
+
+            TokenKind::Emit => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Emit,
+            }),
+
+            TokenKind::Assert => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Assert,
+            }),
+
+            TokenKind::Exit => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::ExitWithCode,
+            }),
+
+            TokenKind::DumpStack => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::DumpStack,
+            }),
+
+            // the type is found by type checking the code compiled so far, so this only works where the stack is
+            // known statically, procedures defined further down are still seen with an empty signature,
+            // and inside of an alias the type is found where the alias is defined rather than where it is used
+            TokenKind::TypeOf => {
+                let (start_position, parameters) =
+                    get_stack_analysis_start(scopes, signatures, current_procedure);
+                let stack = get_stack_at_end(
+                    &procedures[current_procedure],
+                    signatures,
+                    start_position,
+                    parameters,
+                )?
+                .ok_or_else(|| Error {
+                    location: token.location.clone(),
+                    message: "The type of the top of the stack is not known here, because this code can not be reached".to_string(),
+                })?;
+                let typ = stack.last().cloned().ok_or_else(|| Error {
+                    location: token.location.clone(),
+                    message: "Expected a value on the stack for 'typeof'".to_string(),
+                })?;
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: IRKind::PushType { typ },
+                });
+            }
+
             TokenKind::If => {
                 tokenizer.expect_token(TokenKind::OpenBrace)?;
+                let conditional_jump_position = push_conditional_jump(
+                    &mut procedures[current_procedure],
+                    scopes,
+                    token.location.clone(),
+                );
                 scopes.push((
                     Vec::new(),
                     Scope::If {
-                        conditional_jump_position: procedures[current_procedure].len(),
+                        conditional_jump_position,
+                        end_jump_positions: Vec::new(),
+                        location: token.location,
                     },
                 ));
-                procedures[current_procedure].push(IR {
-                    location: token.location,
-                    kind: IRKind::JumpFalse {
-                        relative_position: 0,
-                    },
-                });
+                declare_procedures(
+                    tokenizer,
+                    scopes,
+                    procedures,
+                    signatures,
+                    shadowed_declarations,
+                )?;
             }
 
             TokenKind::While => {
@@ -120,11 +985,83 @@ pub fn compile_ir(
                     Vec::new(),
                     Scope::While {
                         position: procedures[current_procedure].len(),
+                        location: token.location,
                     },
                 ));
             }
 
-            TokenKind::Const => todo!(),
+            TokenKind::Alias => {
+                let name_token = tokenizer.expect_token(TokenKind::Name)?;
+                let name = name_token.data.get_string();
+                // checked now so the error points at the name rather than the end of the body
+                check_not_builtin_type(&name, &name_token.location)?;
+                tokenizer.expect_token(TokenKind::OpenBrace)?;
+                scopes.push((
+                    Vec::new(),
+                    Scope::Alias {
+                        name,
+                        start_position: procedures[current_procedure].len(),
+                        location: token.location,
+                    },
+                ));
+                declare_procedures(
+                    tokenizer,
+                    scopes,
+                    procedures,
+                    signatures,
+                    shadowed_declarations,
+                )?;
+            }
+
+            TokenKind::Const => {
+                let name_token = tokenizer.expect_token(TokenKind::Name)?;
+                let name = name_token.data.get_string();
+                // checked now so the error points at the name rather than the end of the body
+                check_not_builtin_type(&name, &name_token.location)?;
+                tokenizer.expect_token(TokenKind::OpenBrace)?;
+                scopes.push((
+                    Vec::new(),
+                    Scope::Const {
+                        name,
+                        start_position: procedures[current_procedure].len(),
+                        location: token.location,
+                    },
+                ));
+                declare_procedures(
+                    tokenizer,
+                    scopes,
+                    procedures,
+                    signatures,
+                    shadowed_declarations,
+                )?;
+            }
+
+            TokenKind::Let => {
+                let name_token = tokenizer.expect_token(TokenKind::Name)?;
+                let slot = get_next_local_slot(scopes);
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: IRKind::StoreLocal { slot },
+                });
+                declare(
+                    scopes,
+                    shadowed_declarations,
+                    name_token.data.get_string(),
+                    Decl::Local { slot },
+                    name_token.location,
+                )?;
+            }
+
+            TokenKind::StackEffect => {
+                if tokenizer.peek_kind()? != TokenKind::Proc {
+                    return Err(Error {
+                        location: token.location,
+                        message: "Expected a stack effect comment to be directly before a 'proc'"
+                            .to_string(),
+                    });
+                }
+                stack_effect = Some(token);
+            }
 
             TokenKind::Proc => {
                 let name = if tokenizer.peek_kind()? != TokenKind::OpenParenthesis {
@@ -133,29 +1070,112 @@ pub fn compile_ir(
                     None
                 };
 
-                tokenizer.expect_token(TokenKind::OpenParenthesis)?;
-                tokenizer.expect_token(TokenKind::CloseParenthesis)?;
+                let parameters = evaluate_types(
+                    tokenizer,
+                    scopes,
+                    procedures,
+                    signatures,
+                    proc_declarations,
+                    shadowed_declarations,
+                    memory_size,
+                )?;
+                let return_types = if tokenizer.peek_kind()? == TokenKind::RightArrow {
+                    tokenizer.expect_token(TokenKind::RightArrow)?;
+                    evaluate_types(
+                        tokenizer,
+                        scopes,
+                        procedures,
+                        signatures,
+                        proc_declarations,
+                        shadowed_declarations,
+                        memory_size,
+                    )?
+                } else {
+                    Vec::new()
+                };
+                if let Some(stack_effect) = stack_effect.take() {
+                    check_stack_effect(&stack_effect, &parameters, &return_types)?;
+                }
+                let typ = Type::Procedure {
+                    parameters,
+                    return_types,
+                };
+
+                // a procedure without a name or a body is a procedure type
+                if name.is_none() && tokenizer.peek_kind()? != TokenKind::OpenBrace {
+                    procedures[current_procedure].push(IR {
+                        location: token.location,
+                        kind: IRKind::PushType { typ },
+                    });
+                    continue;
+                }
 
                 tokenizer.expect_token(TokenKind::OpenBrace)?;
 
-                if let Some(name_token) = name {
+                let id = if let Some(name_token) = name {
                     let name = name_token.data.get_string();
-                    scopes.last_mut().unwrap().0.push((
+                    // the first declared but not yet defined procedure with this name is this one,
+                    // the conditions of `while` and `elif` are declared ahead of time by the scope around them
+                    let decl = scopes
+                        .iter_mut()
+                        .rev()
+                        .find_map(|(decls, _)| {
+                            decls.iter_mut().find(|(decl_name, decl, _)| {
+                                decl_name == &name && matches!(decl, Decl::DeclaredProc { .. })
+                            })
+                        })
+                        .map(|(_, decl, _)| decl);
+                    let id = match decl {
+                        Some(decl) => {
+                            let id = if let Decl::DeclaredProc { id } = *decl {
+                                id
+                            } else {
+                                unreachable!()
+                            };
+                            *decl = Decl::Proc { id };
+                            id
+                        }
+                        None => {
+                            return Err(Error {
+                                location: name_token.location,
+                                message: format!(
+                                    "Procedure '{}' can not be defined here, it was not declared ahead of time",
+                                    name
+                                ),
+                            })
+                        }
+                    };
+                    proc_declarations.push(ProcDeclaration {
                         name,
-                        Decl::Proc {
-                            id: procedures.len(),
-                        },
-                    ));
-                    scopes.push((
-                        Vec::new(),
-                        Scope::Proc {
-                            id: procedures.len(),
-                        },
-                    ));
-                    procedures.push(Vec::new());
+                        id,
+                        location: name_token.location,
+                    });
+                    signatures[id] = typ;
+                    id
                 } else {
-                    todo!()
-                }
+                    let id = procedures.len();
+                    procedures[current_procedure].push(IR {
+                        location: token.location.clone(),
+                        kind: IRKind::PushProc { id },
+                    });
+                    procedures.push(Vec::new());
+                    signatures.push(typ);
+                    id
+                };
+                scopes.push((
+                    Vec::new(),
+                    Scope::Proc {
+                        id,
+                        location: token.location,
+                    },
+                ));
+                declare_procedures(
+                    tokenizer,
+                    scopes,
+                    procedures,
+                    signatures,
+                    shadowed_declarations,
+                )?;
             }
 
             TokenKind::Call => procedures[current_procedure].push(IR {
@@ -178,40 +1198,194 @@ pub fn compile_ir(
                 kind: IRKind::Swap,
             }),
 
-            TokenKind::Memory => todo!(),
+            TokenKind::Over => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Over,
+            }),
 
-            TokenKind::OpenParenthesis => todo!(),
-            TokenKind::CloseParenthesis => todo!(),
+            TokenKind::Rot => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Rot,
+            }),
 
-            TokenKind::OpenBrace => match &scopes.last().unwrap().1 {
-                &Scope::While { position } => {
+            TokenKind::TwoDup => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::TwoDup,
+            }),
+
+            TokenKind::Nip => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Nip,
+            }),
+
+            TokenKind::Tuck => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Tuck,
+            }),
+
+            TokenKind::DropN | TokenKind::DupN | TokenKind::Pick | TokenKind::Roll => {
+                // the count has to be known at compile time so the stack effect can be type checked
+                let count = match procedures[current_procedure].last() {
+                    Some(IR {
+                        kind: IRKind::PushInt { value },
+                        ..
+                    }) if *value >= 0
+                        && can_replace_last(&procedures[current_procedure], scopes) =>
+                    {
+                        *value as usize
+                    }
+                    _ => {
+                        return Err(Error {
+                            location: token.location,
+                            message: format!(
+                                "Expected '{}' to be preceded by a constant {}",
+                                token.kind,
+                                if let TokenKind::Pick | TokenKind::Roll = token.kind {
+                                    "depth"
+                                } else {
+                                    "count"
+                                }
+                            ),
+                        })
+                    }
+                };
+                procedures[current_procedure].pop();
+                procedures[current_procedure].push(IR {
+                    location: token.location,
+                    kind: match token.kind {
+                        TokenKind::DropN => IRKind::DropN { count },
+                        TokenKind::DupN => IRKind::DupN { count },
+                        TokenKind::Pick => IRKind::Pick { depth: count },
+                        TokenKind::Roll => IRKind::Roll { depth: count },
+                        _ => unreachable!(),
+                    },
+                });
+            }
+
+            TokenKind::Memory => {
+                let name_token = tokenizer.expect_token(TokenKind::Name)?;
+                let name = name_token.data.get_string();
+                let size_token = tokenizer.expect_token(TokenKind::Integer)?;
+                let size = size_token.data.get_integer();
+                if size < 0 {
+                    return Err(Error {
+                        location: size_token.location,
+                        message: format!("Memory '{}' can not have a negative size", name),
+                    });
+                }
+                declare(
+                    scopes,
+                    shadowed_declarations,
+                    name,
+                    Decl::Memory {
+                        offset: *memory_size,
+                        size: size as usize,
+                    },
+                    name_token.location,
+                )?;
+                *memory_size += size as usize;
+            }
+
+            TokenKind::Load | TokenKind::At => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Load,
+            }),
+
+            TokenKind::Store | TokenKind::LeftArrow => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Store,
+            }),
+
+            // parentheses are only used by signatures for now, which read their own tokens
+            TokenKind::OpenParenthesis | TokenKind::CloseParenthesis => {
+                return Err(Error {
+                    location: token.location,
+                    message: format!("Unexpected '{}'", token.kind),
+                })
+            }
+
+            TokenKind::OpenBrace => match scopes.last().unwrap().1.clone() {
+                Scope::While { position, location } => {
+                    let conditional_jump_position = push_conditional_jump(
+                        &mut procedures[current_procedure],
+                        scopes,
+                        token.location,
+                    );
                     scopes.pop().unwrap();
                     scopes.push((
                         Vec::new(),
                         Scope::WhileBody {
                             while_position: position,
-                            conditional_jump_position: procedures[current_procedure].len(),
+                            conditional_jump_position,
+                            location,
                         },
                     ));
-                    procedures[current_procedure].push(IR {
-                        location: token.location,
-                        kind: IRKind::JumpFalse {
-                            relative_position: 0,
+                    declare_procedures(
+                        tokenizer,
+                        scopes,
+                        procedures,
+                        signatures,
+                        shadowed_declarations,
+                    )?;
+                }
+
+                Scope::Elif {
+                    end_jump_positions,
+                    location,
+                } => {
+                    let conditional_jump_position = push_conditional_jump(
+                        &mut procedures[current_procedure],
+                        scopes,
+                        token.location,
+                    );
+                    scopes.pop().unwrap();
+                    scopes.push((
+                        Vec::new(),
+                        Scope::If {
+                            conditional_jump_position,
+                            end_jump_positions,
+                            location,
                         },
-                    });
+                    ));
+                    declare_procedures(
+                        tokenizer,
+                        scopes,
+                        procedures,
+                        signatures,
+                        shadowed_declarations,
+                    )?;
                 }
 
                 _ => {
-                    scopes.push((Vec::new(), Scope::Block));
+                    scopes.push((
+                        Vec::new(),
+                        Scope::Block {
+                            location: token.location,
+                        },
+                    ));
+                    declare_procedures(
+                        tokenizer,
+                        scopes,
+                        procedures,
+                        signatures,
+                        shadowed_declarations,
+                    )?;
                 }
             },
 
             TokenKind::CloseBrace => {
+                if let Scope::Global | Scope::ConstantEval { .. } = scopes.last().unwrap().1 {
+                    return Err(Error {
+                        location: token.location,
+                        message: "Unexpected '}', there is no '{' for it to close".to_string(),
+                    });
+                }
                 let scope = scopes.pop().unwrap().1;
                 match scope {
                     Scope::WhileBody {
                         while_position,
                         conditional_jump_position,
+                        location: _,
                     } => {
                         let current_pos = procedures[current_procedure].len();
                         procedures[current_procedure].push(IR {
@@ -223,7 +1397,9 @@ pub fn compile_ir(
 
                         let current_pos = procedures[current_procedure].len();
                         let ir = &mut procedures[current_procedure][conditional_jump_position];
-                        if let IRKind::JumpFalse { relative_position } = &mut ir.kind {
+                        if let IRKind::JumpFalse { relative_position }
+                        | IRKind::JumpTrue { relative_position } = &mut ir.kind
+                        {
                             *relative_position =
                                 current_pos as isize - conditional_jump_position as isize;
                         } else {
@@ -233,27 +1409,56 @@ pub fn compile_ir(
 
                     Scope::If {
                         conditional_jump_position,
+                        mut end_jump_positions,
+                        location: _,
                     } => {
-                        if tokenizer.peek_kind()? == TokenKind::Else {
-                            tokenizer.expect_token(TokenKind::Else)?;
-                            tokenizer.expect_token(TokenKind::OpenBrace)?;
-                            scopes.push((
-                                Vec::new(),
-                                Scope::Else {
-                                    end_of_then_jump_position: procedures[current_procedure].len(),
-                                },
-                            ));
+                        let next_kind = tokenizer.peek_kind()?;
+                        if next_kind == TokenKind::Else || next_kind == TokenKind::Elif {
+                            let location = tokenizer.next_token()?.location;
+                            end_jump_positions.push(procedures[current_procedure].len());
                             procedures[current_procedure].push(IR {
-                                location: token.location,
+                                location: token.location.clone(),
                                 kind: IRKind::Jump {
                                     relative_position: 0,
                                 },
                             });
+                            if next_kind == TokenKind::Else {
+                                tokenizer.expect_token(TokenKind::OpenBrace)?;
+                                scopes.push((
+                                    Vec::new(),
+                                    Scope::Else {
+                                        end_jump_positions,
+                                        location,
+                                    },
+                                ));
+                                declare_procedures(
+                                    tokenizer,
+                                    scopes,
+                                    procedures,
+                                    signatures,
+                                    shadowed_declarations,
+                                )?;
+                            } else {
+                                scopes.push((
+                                    Vec::new(),
+                                    Scope::Elif {
+                                        end_jump_positions,
+                                        location,
+                                    },
+                                ));
+                            }
+                        } else {
+                            patch_end_jumps(
+                                &mut procedures[current_procedure],
+                                &end_jump_positions,
+                            );
                         }
 
                         let current_pos = procedures[current_procedure].len();
                         let ir = &mut procedures[current_procedure][conditional_jump_position];
-                        if let IRKind::JumpFalse { relative_position } = &mut ir.kind {
+                        if let IRKind::JumpFalse { relative_position }
+                        | IRKind::JumpTrue { relative_position } = &mut ir.kind
+                        {
                             *relative_position =
                                 current_pos as isize - conditional_jump_position as isize;
                         } else {
@@ -262,31 +1467,79 @@ pub fn compile_ir(
                     }
 
                     Scope::Else {
-                        end_of_then_jump_position,
+                        end_jump_positions,
+                        location: _,
                     } => {
-                        let current_pos = procedures[current_procedure].len();
-                        let ir = &mut procedures[current_procedure][end_of_then_jump_position];
-                        if let IRKind::Jump { relative_position } = &mut ir.kind {
-                            *relative_position =
-                                current_pos as isize - end_of_then_jump_position as isize;
-                        } else {
-                            unreachable!()
-                        }
+                        patch_end_jumps(&mut procedures[current_procedure], &end_jump_positions);
                     }
 
-                    Scope::Proc { id } => {
+                    Scope::Proc { id, location: _ } => {
                         procedures[id].push(IR {
                             location: token.location,
                             kind: IRKind::Return,
                         });
                     }
 
-                    Scope::Block => {}
+                    Scope::Const {
+                        name,
+                        start_position,
+                        location,
+                    } => {
+                        let ir = procedures[current_procedure].split_off(start_position);
+                        let values = evaluate_constant(procedures, ir, token.location.clone())?;
+                        if values.len() != 1 {
+                            return Err(Error {
+                                location: token.location,
+                                message: format!(
+                                    "Expected const '{}' to evaluate to 1 value, but got {}",
+                                    name,
+                                    values.len()
+                                ),
+                            });
+                        }
+
+                        let kind = match values.into_iter().next().unwrap() {
+                            Value::Integer(value) => IRKind::PushInt { value },
+                            Value::Float(value) => IRKind::PushFloat { value },
+                            Value::Procedure(id) => IRKind::PushProc { id },
+                            Value::Type(typ) => IRKind::PushType { typ },
+                            Value::Bool(value) => IRKind::PushBool { value },
+                        };
+                        declare(
+                            scopes,
+                            shadowed_declarations,
+                            name,
+                            Decl::Const {
+                                ir: vec![IR {
+                                    location: token.location,
+                                    kind,
+                                }],
+                            },
+                            location,
+                        )?;
+                    }
+
+                    Scope::Alias {
+                        name,
+                        start_position,
+                        location,
+                    } => {
+                        let ir = procedures[current_procedure].split_off(start_position);
+                        declare(
+                            scopes,
+                            shadowed_declarations,
+                            name,
+                            Decl::Alias { ir },
+                            location,
+                        )?;
+                    }
+
+                    Scope::Block { location: _ } => {}
 
                     _ => {
                         return Err(Error {
                             location: token.location,
-                            message: format!("Unexpected token '{:?}'", token.kind),
+                            message: format!("Unexpected token '{}'", token.kind),
                         });
                     }
                 }
@@ -297,6 +1550,31 @@ pub fn compile_ir(
                 kind: IRKind::Not,
             }),
 
+            TokenKind::Neg => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Neg,
+            }),
+
+            TokenKind::And => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::And,
+            }),
+
+            TokenKind::Or => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Or,
+            }),
+
+            TokenKind::Max => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Max,
+            }),
+
+            TokenKind::Min => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Min,
+            }),
+
             TokenKind::Plus => procedures[current_procedure].push(IR {
                 location: token.location,
                 kind: IRKind::Add,
@@ -317,6 +1595,36 @@ pub fn compile_ir(
                 kind: IRKind::Div,
             }),
 
+            TokenKind::Percent => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::Mod,
+            }),
+
+            TokenKind::Ampersand => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::BitAnd,
+            }),
+
+            TokenKind::Pipe => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::BitOr,
+            }),
+
+            TokenKind::Caret => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::BitXor,
+            }),
+
+            TokenKind::ShiftLeft => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::ShiftLeft,
+            }),
+
+            TokenKind::ShiftRight => procedures[current_procedure].push(IR {
+                location: token.location,
+                kind: IRKind::ShiftRight,
+            }),
+
             TokenKind::LessThan => procedures[current_procedure].push(IR {
                 location: token.location,
                 kind: IRKind::LessThan,
@@ -347,20 +1655,22 @@ pub fn compile_ir(
                 kind: IRKind::NotEqual,
             }),
 
+            // an else or elif that belongs to an if is taken when the if's '}' is reached
+            TokenKind::Else | TokenKind::Elif => {
+                return Err(Error {
+                    location: token.location,
+                    message: format!("'{}' without a matching 'if'", token.kind),
+                });
+            }
+
             _ => {
                 return Err(Error {
                     location: token.location,
-                    message: format!("Unexpected token '{:?}'", token.kind),
+                    message: format!("Unexpected token '{}'", token.kind),
                 });
             }
         }
     }
 
-    assert_eq!(tokenizer.peek_kind()?, TokenKind::EndOfFile);
-    procedures[0].push(IR {
-        location: tokenizer.next_token()?.location,
-        kind: IRKind::Exit,
-    });
-
     Ok(())
 }
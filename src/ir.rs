@@ -1,4 +1,4 @@
-use crate::common::SourceLocation;
+use crate::{common::SourceLocation, types::Type};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct IR {
@@ -10,13 +10,40 @@ pub struct IR {
 pub enum IRKind {
     Exit,
 
-    PushProc { id: usize },
-    PushInt { value: isize },
+    PushProc {
+        id: usize,
+    },
+    PushInt {
+        value: i64,
+    },
+    PushFloat {
+        value: f64,
+    },
+    PushBool {
+        value: bool,
+    },
+    PushType {
+        typ: Type,
+    },
+    PushMemory {
+        offset: usize,
+        size: usize,
+    },
+    /// Pushes the value at `index` in the constant pool the procedures are run with
+    PushConst {
+        index: usize,
+    },
 
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     LessThan,
     GreaterThan,
     LessThanEqual,
@@ -24,16 +51,100 @@ pub enum IRKind {
     Equal,
     NotEqual,
     Not,
+    Neg,
+    /// Both operands have already been evaluated, so unlike most languages this does not short circuit
+    And,
+    Or,
+    Max,
+    Min,
 
     Dup,
     Drop,
     Swap,
+    Over,
+    Rot,
+    TwoDup,
+    Nip,
+    Tuck,
+    DropN {
+        count: usize,
+    },
+    /// Duplicates the top `count` values, keeping their order
+    DupN {
+        count: usize,
+    },
+    /// Copies the value `depth` values below the top to the top, `0` is the same as `Dup`
+    Pick {
+        depth: usize,
+    },
+    /// Moves the value `depth` values below the top to the top, `2` is the same as `Rot`
+    Roll {
+        depth: usize,
+    },
 
-    Jump { relative_position: isize },
-    JumpFalse { relative_position: isize },
+    Load,
+    Store,
+
+    /// Pops the top value into the local `slot` of the current procedure call
+    StoreLocal {
+        slot: usize,
+    },
+    LoadLocal {
+        slot: usize,
+    },
+
+    Jump {
+        relative_position: isize,
+    },
+    JumpFalse {
+        relative_position: isize,
+    },
+    JumpTrue {
+        relative_position: isize,
+    },
 
     Call,
+    /// Calls a procedure by replacing the current one, so it returns directly to our caller
+    TailCall,
     Return,
 
     Print,
+    PrintStr,
+    /// Prints an integer in base 16 with a `0x` prefix
+    PrintHex,
+    /// Prints an integer in base 2 with a `0b` prefix
+    PrintBin,
+    /// Prints like `Print` but without the trailing newline
+    Emit,
+    /// Stops execution with an error if the bool on top of the stack is false
+    Assert,
+    /// Stops the whole program with the integer on top of the stack as its exit code
+    ExitWithCode,
+    /// Prints the whole stack from the bottom up as `<count> values...` without changing it
+    DumpStack,
+}
+
+/// Gets the number of local slots `procedure` uses, one more than the highest slot
+pub fn get_local_count(procedure: &[IR]) -> usize {
+    procedure
+        .iter()
+        .filter_map(|ir| match ir.kind {
+            IRKind::StoreLocal { slot } | IRKind::LoadLocal { slot } => Some(slot + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Gets the number of bytes needed to hold every memory region referenced by `procedures`
+pub fn get_memory_size(procedures: &[Vec<IR>]) -> usize {
+    procedures
+        .iter()
+        .flatten()
+        .map(|ir| match ir.kind {
+            IRKind::PushMemory { offset, size } => offset + size,
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
 }
@@ -0,0 +1,922 @@
+use std::{
+    fmt::Display,
+    io::{stdout, Write},
+    ops::Range,
+};
+
+use crate::{
+    common::{Error, SourceLocation},
+    compile::{GlobalDeclaration, GlobalDeclarationKind},
+    ir::{get_memory_size, IRKind, IR},
+    types::Type,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Procedure(usize),
+    Type(Type),
+}
+
+impl Value {
+    pub fn get_integer(self: &Value) -> i64 {
+        if let Value::Integer(value) = self {
+            *value
+        } else {
+            unreachable!()
+        }
+    }
+
+    pub fn get_float(self: &Value) -> f64 {
+        if let Value::Float(value) = self {
+            *value
+        } else {
+            unreachable!()
+        }
+    }
+
+    pub fn get_bool(self: &Value) -> bool {
+        if let Value::Bool(value) = self {
+            *value
+        } else {
+            unreachable!()
+        }
+    }
+
+    pub fn get_procedure(self: &Value) -> usize {
+        if let Value::Procedure(id) = self {
+            *id
+        } else {
+            unreachable!()
+        }
+    }
+
+    pub fn get_type(self: &Value) -> Type {
+        if let Value::Type(typ) = self {
+            typ.clone()
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Procedure(id) => write!(f, "proc {}", id),
+            Value::Type(typ) => write!(f, "{}", typ),
+        }
+    }
+}
+
+fn pop_value(stack: &mut Vec<Value>, ir: &IR) -> Result<Value, Error> {
+    stack.pop().ok_or_else(|| Error {
+        location: ir.location.clone(),
+        message: "Stack underflow".to_string(),
+    })
+}
+
+fn pop_integer(stack: &mut Vec<Value>, ir: &IR) -> Result<i64, Error> {
+    match pop_value(stack, ir)? {
+        Value::Integer(value) => Ok(value),
+        value => Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected an integer, but got '{}'", value),
+        }),
+    }
+}
+
+enum Numbers {
+    Integer(i64, i64),
+    Float(f64, f64),
+}
+
+/// Pops two operands which must both be integers or both be floats
+fn pop_numbers(stack: &mut Vec<Value>, ir: &IR) -> Result<Numbers, Error> {
+    let b = pop_value(stack, ir)?;
+    let a = pop_value(stack, ir)?;
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Numbers::Integer(a, b)),
+        (Value::Float(a), Value::Float(b)) => Ok(Numbers::Float(a, b)),
+        (a, b) => Err(Error {
+            location: ir.location.clone(),
+            message: format!(
+                "Expected two integers or two floats, but got '{}' and '{}'",
+                a, b
+            ),
+        }),
+    }
+}
+
+/// Pops two values of the same kind, without type checking they could be anything
+fn pop_comparable_values(stack: &mut Vec<Value>, ir: &IR) -> Result<(Value, Value), Error> {
+    let b = pop_value(stack, ir)?;
+    let a = pop_value(stack, ir)?;
+    if std::mem::discriminant(&a) != std::mem::discriminant(&b) {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: "Cannot compare values of different types".to_string(),
+        });
+    }
+    Ok((a, b))
+}
+
+fn pop_bool(stack: &mut Vec<Value>, ir: &IR) -> Result<bool, Error> {
+    match pop_value(stack, ir)? {
+        Value::Bool(value) => Ok(value),
+        value => Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected a bool, but got '{}'", value),
+        }),
+    }
+}
+
+fn pop_procedure(stack: &mut Vec<Value>, ir: &IR) -> Result<usize, Error> {
+    match pop_value(stack, ir)? {
+        Value::Procedure(id) => Ok(id),
+        value => Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected a procedure, but got '{}'", value),
+        }),
+    }
+}
+
+/// Takes the result of an `overflowing_*` operation, failing if it overflowed and overflow is checked
+fn check_overflow(
+    mode: OverflowMode,
+    (value, overflowed): (i64, bool),
+    ir: &IR,
+) -> Result<i64, Error> {
+    if overflowed && mode == OverflowMode::Checked {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: "Integer overflow".to_string(),
+        });
+    }
+    Ok(value)
+}
+
+fn pop_shift_amount(stack: &mut Vec<Value>, ir: &IR) -> Result<u32, Error> {
+    let amount = pop_integer(stack, ir)?;
+    if amount < 0 || amount >= i64::BITS as i64 {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: format!("Invalid shift amount {}", amount),
+        });
+    }
+    Ok(amount as u32)
+}
+
+fn get_memory_range(
+    memory: &[u8],
+    address: i64,
+    length: usize,
+    ir: &IR,
+) -> Result<Range<usize>, Error> {
+    if address < 0 || address as usize + length > memory.len() {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: format!("Memory address {} is out of bounds", address),
+        });
+    }
+    Ok(address as usize..address as usize + length)
+}
+
+pub const DEFAULT_MAX_RECURSION_DEPTH: usize = 1 << 20;
+
+/// What happens when integer arithmetic does not fit in 64 bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowMode {
+    /// The result wraps around in two's complement
+    Wrapping,
+    /// The instruction fails with an error
+    Checked,
+}
+
+pub struct RunOptions<'a> {
+    /// How many calls can be active at once before execution is stopped with an error
+    pub max_recursion_depth: usize,
+    /// How many more instructions can be executed, `None` means there is no limit
+    pub fuel: Option<u64>,
+    pub overflow: OverflowMode,
+    /// Where `print` and `print_str` write to
+    pub out: &'a mut dyn Write,
+    /// The values `PushConst` instructions refer to
+    pub constants: &'a [Value],
+}
+
+impl<'a> RunOptions<'a> {
+    pub fn new(out: &'a mut dyn Write) -> RunOptions<'a> {
+        RunOptions {
+            max_recursion_depth: DEFAULT_MAX_RECURSION_DEPTH,
+            fuel: None,
+            overflow: OverflowMode::Wrapping,
+            out,
+            constants: &[],
+        }
+    }
+}
+
+/// Runs the program, returning its exit code
+pub fn run_ir(procedures: &[Vec<IR>]) -> Result<i64, Error> {
+    run_ir_with(procedures, &mut RunOptions::new(&mut stdout()))
+}
+
+pub fn run_ir_with(procedures: &[Vec<IR>], options: &mut RunOptions) -> Result<i64, Error> {
+    match Machine::new(procedures, 0, Vec::new()).run(procedures, options)? {
+        (StepResult::Halted(code), _) => Ok(code),
+        _ => unreachable!("there are no breakpoints"),
+    }
+}
+
+fn write_output(options: &mut RunOptions, text: &str, ir: &IR) -> Result<(), Error> {
+    options
+        .out
+        .write_all(text.as_bytes())
+        .map_err(|error| Error {
+            location: ir.location.clone(),
+            message: format!("Unable to write output: {}", error),
+        })
+}
+
+/// Runs the top level procedure called `name` with `stack` as its arguments, returning the final stack.
+/// The global code is not run, so memory starts zeroed
+pub fn run_named(
+    procedures: &[Vec<IR>],
+    global_declarations: &[GlobalDeclaration],
+    name: &str,
+    stack: Vec<Value>,
+    options: &mut RunOptions,
+) -> Result<Vec<Value>, Error> {
+    let id = global_declarations
+        .iter()
+        .find_map(|declaration| match declaration.kind {
+            GlobalDeclarationKind::Proc { id } if declaration.name == name => Some(id),
+            _ => None,
+        });
+    match id {
+        Some(id) => run_procedure_with_stack(procedures, id, stack, options),
+        // the name does not come from any source code
+        None => Err(Error {
+            location: SourceLocation {
+                filepath: "<run_named>".to_string(),
+                position: 0,
+                line: 1,
+                column: 1,
+                length: 0,
+            },
+            message: format!("There is no procedure called '{}'", name),
+        }),
+    }
+}
+
+/// Runs procedures starting from the procedure `id` until an `Exit` is reached, returning the final stack
+pub fn run_procedure(
+    procedures: &[Vec<IR>],
+    id: usize,
+    options: &mut RunOptions,
+) -> Result<Vec<Value>, Error> {
+    run_procedure_with_stack(procedures, id, Vec::new(), options)
+}
+
+/// Like `run_procedure` but starts with `stack`, returning from the starting procedure also stops execution
+pub fn run_procedure_with_stack(
+    procedures: &[Vec<IR>],
+    id: usize,
+    stack: Vec<Value>,
+    options: &mut RunOptions,
+) -> Result<Vec<Value>, Error> {
+    let (_, stack) = Machine::new(procedures, id, stack).run(procedures, options)?;
+    Ok(stack)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Continue,
+    /// The program stopped with an exit code, which is 0 unless it was stopped with `exit`
+    Halted(i64),
+    /// The next instruction matches a breakpoint and has not been run yet,
+    /// the next step runs it without stopping again
+    Breakpoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Any instruction that came from this line and column of the file `filepath`
+    Location {
+        filepath: String,
+        line: usize,
+        column: usize,
+    },
+    /// The instruction at `position` in the procedure `procedure`
+    Instruction { procedure: usize, position: usize },
+}
+
+/// The state of a running program, kept between calls so execution can be resumed where it stopped
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub stack: Vec<Value>,
+    /// The procedure and position each call that has not returned yet goes back to
+    pub return_stack: Vec<(usize, usize)>,
+    /// The locals of every procedure call that has not returned yet, the innermost call is last
+    pub frames: Vec<Vec<Option<Value>>>,
+    pub memory: Vec<u8>,
+    /// The procedure being run
+    pub procedure: usize,
+    /// The position of the next instruction in `procedure`
+    pub ip: usize,
+    breakpoints: Vec<Breakpoint>,
+    /// Set after stopping at a breakpoint, so that the instruction is run by the next step
+    resuming: bool,
+}
+
+impl Machine {
+    /// Creates a machine that will start at the beginning of the procedure `id` with `stack` already on the stack,
+    /// which is where a procedure expects its parameters
+    pub fn new(procedures: &[Vec<IR>], id: usize, stack: Vec<Value>) -> Machine {
+        Machine {
+            stack,
+            return_stack: Vec::new(),
+            frames: vec![Vec::new()],
+            memory: vec![0u8; get_memory_size(procedures)],
+            procedure: id,
+            ip: 0,
+            breakpoints: Vec::new(),
+            resuming: false,
+        }
+    }
+
+    pub fn add_breakpoint(self: &mut Machine, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    fn is_breakpoint(self: &Machine, ir: &IR) -> bool {
+        self.breakpoints.iter().any(|breakpoint| match breakpoint {
+            Breakpoint::Location {
+                filepath,
+                line,
+                column,
+            } => {
+                ir.location.filepath == *filepath
+                    && ir.location.line == *line
+                    && ir.location.column == *column
+            }
+            Breakpoint::Instruction {
+                procedure,
+                position,
+            } => *procedure == self.procedure && *position == self.ip,
+        })
+    }
+
+    /// Runs until the program halts, the procedure it started in returns or a breakpoint is hit,
+    /// and returns why it stopped along with a copy of the stack at that point.
+    /// Memory declared since the last call is added zeroed, so `procedures` can grow between calls
+    pub fn run(
+        self: &mut Machine,
+        procedures: &[Vec<IR>],
+        options: &mut RunOptions,
+    ) -> Result<(StepResult, Vec<Value>), Error> {
+        let memory_size = get_memory_size(procedures);
+        if self.memory.len() < memory_size {
+            self.memory.resize(memory_size, 0);
+        }
+        loop {
+            match self.step(procedures, options)? {
+                StepResult::Continue => {}
+                result => return Ok((result, self.stack.clone())),
+            }
+        }
+    }
+
+    /// Runs the next instruction
+    pub fn step(
+        self: &mut Machine,
+        procedures: &[Vec<IR>],
+        options: &mut RunOptions,
+    ) -> Result<StepResult, Error> {
+        // the end of a procedure is checked after each instruction, this catches starting outside of one
+        let ir = match procedures.get(self.procedure) {
+            Some(procedure) if self.ip < procedure.len() => &procedure[self.ip],
+            procedure => {
+                return Err(Error {
+                    // there is no instruction to point at, so the last one before the end is used if there is one
+                    location: match procedure.and_then(|procedure| procedure.last()) {
+                        Some(ir) => ir.location.clone(),
+                        None => SourceLocation {
+                            filepath: "<ir>".to_string(),
+                            position: 0,
+                            line: 1,
+                            column: 1,
+                            length: 0,
+                        },
+                    },
+                    message: if procedure.is_some() {
+                        format!(
+                            "Execution went outside of procedure {} at instruction {}",
+                            self.procedure, self.ip
+                        )
+                    } else {
+                        format!("Procedure {} does not exist", self.procedure)
+                    },
+                });
+            }
+        };
+        if !self.breakpoints.is_empty() {
+            if !self.resuming && self.is_breakpoint(ir) {
+                self.resuming = true;
+                return Ok(StepResult::Breakpoint);
+            }
+            self.resuming = false;
+        }
+
+        let position = self.ip;
+        self.ip += 1;
+
+        if let Some(fuel) = &mut options.fuel {
+            if *fuel == 0 {
+                return Err(Error {
+                    location: ir.location.clone(),
+                    message: "Execution budget exhausted".to_string(),
+                });
+            }
+            *fuel -= 1;
+        }
+
+        match &ir.kind {
+            // the position stays on the instruction that halted, so stepping again halts again
+            IRKind::Exit => {
+                self.ip = position;
+                return Ok(StepResult::Halted(0));
+            }
+
+            IRKind::PushProc { id } => self.stack.push(Value::Procedure(*id)),
+            IRKind::PushInt { value } => self.stack.push(Value::Integer(*value)),
+            IRKind::PushFloat { value } => self.stack.push(Value::Float(*value)),
+            IRKind::PushBool { value } => self.stack.push(Value::Bool(*value)),
+            IRKind::PushType { typ } => self.stack.push(Value::Type(typ.clone())),
+            IRKind::PushMemory { offset, size: _ } => {
+                self.stack.push(Value::Integer(*offset as i64))
+            }
+            IRKind::PushConst { index } => {
+                let value = options.constants.get(*index).ok_or_else(|| Error {
+                    location: ir.location.clone(),
+                    message: format!("Constant {} does not exist", index),
+                })?;
+                self.stack.push(value.clone());
+            }
+
+            IRKind::Add => {
+                let value = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => {
+                        Value::Integer(check_overflow(options.overflow, a.overflowing_add(b), ir)?)
+                    }
+                    Numbers::Float(a, b) => Value::Float(a + b),
+                };
+                self.stack.push(value);
+            }
+
+            IRKind::Sub => {
+                let value = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => {
+                        Value::Integer(check_overflow(options.overflow, a.overflowing_sub(b), ir)?)
+                    }
+                    Numbers::Float(a, b) => Value::Float(a - b),
+                };
+                self.stack.push(value);
+            }
+
+            IRKind::Mul => {
+                let value = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => {
+                        Value::Integer(check_overflow(options.overflow, a.overflowing_mul(b), ir)?)
+                    }
+                    Numbers::Float(a, b) => Value::Float(a * b),
+                };
+                self.stack.push(value);
+            }
+
+            IRKind::Div => {
+                let value = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(_, 0) => {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: "Division by zero".to_string(),
+                        })
+                    }
+                    Numbers::Integer(a, b) => {
+                        Value::Integer(check_overflow(options.overflow, a.overflowing_div(b), ir)?)
+                    }
+                    Numbers::Float(a, b) => Value::Float(a / b),
+                };
+                self.stack.push(value);
+            }
+
+            IRKind::Mod => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                if b == 0 {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Division by zero".to_string(),
+                    });
+                }
+                self.stack.push(Value::Integer(check_overflow(
+                    options.overflow,
+                    a.overflowing_rem(b),
+                    ir,
+                )?));
+            }
+
+            IRKind::BitAnd => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a & b));
+            }
+
+            IRKind::BitOr => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a | b));
+            }
+
+            IRKind::BitXor => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a ^ b));
+            }
+
+            IRKind::ShiftLeft => {
+                let b = pop_shift_amount(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a << b));
+            }
+
+            IRKind::ShiftRight => {
+                let b = pop_shift_amount(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a >> b));
+            }
+
+            IRKind::LessThan => {
+                let result = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => a < b,
+                    Numbers::Float(a, b) => a < b,
+                };
+                self.stack.push(Value::Bool(result));
+            }
+
+            IRKind::GreaterThan => {
+                let result = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => a > b,
+                    Numbers::Float(a, b) => a > b,
+                };
+                self.stack.push(Value::Bool(result));
+            }
+
+            IRKind::LessThanEqual => {
+                let result = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => a <= b,
+                    Numbers::Float(a, b) => a <= b,
+                };
+                self.stack.push(Value::Bool(result));
+            }
+
+            IRKind::GreaterThanEqual => {
+                let result = match pop_numbers(&mut self.stack, ir)? {
+                    Numbers::Integer(a, b) => a >= b,
+                    Numbers::Float(a, b) => a >= b,
+                };
+                self.stack.push(Value::Bool(result));
+            }
+
+            IRKind::Equal => {
+                let (a, b) = pop_comparable_values(&mut self.stack, ir)?;
+                self.stack.push(Value::Bool(a == b));
+            }
+
+            IRKind::NotEqual => {
+                let (a, b) = pop_comparable_values(&mut self.stack, ir)?;
+                self.stack.push(Value::Bool(a != b));
+            }
+
+            IRKind::Not => {
+                let a = pop_bool(&mut self.stack, ir)?;
+                self.stack.push(Value::Bool(!a));
+            }
+
+            IRKind::And => {
+                let b = pop_bool(&mut self.stack, ir)?;
+                let a = pop_bool(&mut self.stack, ir)?;
+                self.stack.push(Value::Bool(a && b));
+            }
+
+            IRKind::Or => {
+                let b = pop_bool(&mut self.stack, ir)?;
+                let a = pop_bool(&mut self.stack, ir)?;
+                self.stack.push(Value::Bool(a || b));
+            }
+
+            IRKind::Max => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a.max(b)));
+            }
+
+            IRKind::Min => {
+                let b = pop_integer(&mut self.stack, ir)?;
+                let a = pop_integer(&mut self.stack, ir)?;
+                self.stack.push(Value::Integer(a.min(b)));
+            }
+
+            IRKind::Neg => {
+                let value = match pop_value(&mut self.stack, ir)? {
+                    Value::Integer(a) => {
+                        Value::Integer(check_overflow(options.overflow, a.overflowing_neg(), ir)?)
+                    }
+                    Value::Float(a) => Value::Float(-a),
+                    value => {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!("Expected an integer or a float, but got '{}'", value),
+                        })
+                    }
+                };
+                self.stack.push(value);
+            }
+
+            IRKind::Dup => {
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(a.clone());
+                self.stack.push(a);
+            }
+
+            IRKind::Drop => {
+                pop_value(&mut self.stack, ir)?;
+            }
+
+            IRKind::Swap => {
+                let b = pop_value(&mut self.stack, ir)?;
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+
+            IRKind::Over => {
+                let b = pop_value(&mut self.stack, ir)?;
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(a.clone());
+                self.stack.push(b);
+                self.stack.push(a);
+            }
+
+            IRKind::Rot => {
+                let c = pop_value(&mut self.stack, ir)?;
+                let b = pop_value(&mut self.stack, ir)?;
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(b);
+                self.stack.push(c);
+                self.stack.push(a);
+            }
+
+            IRKind::TwoDup => {
+                let b = pop_value(&mut self.stack, ir)?;
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(a.clone());
+                self.stack.push(b.clone());
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+
+            IRKind::Nip => {
+                let b = pop_value(&mut self.stack, ir)?;
+                pop_value(&mut self.stack, ir)?;
+                self.stack.push(b);
+            }
+
+            IRKind::Tuck => {
+                let b = pop_value(&mut self.stack, ir)?;
+                let a = pop_value(&mut self.stack, ir)?;
+                self.stack.push(b.clone());
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+
+            IRKind::DropN { count } => {
+                if self.stack.len() < *count {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Stack underflow".to_string(),
+                    });
+                }
+                self.stack.truncate(self.stack.len() - count);
+            }
+
+            IRKind::DupN { count } => {
+                if self.stack.len() < *count {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Stack underflow".to_string(),
+                    });
+                }
+                self.stack.extend_from_within(self.stack.len() - count..);
+            }
+
+            IRKind::Pick { depth } => {
+                if self.stack.len() <= *depth {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Stack underflow".to_string(),
+                    });
+                }
+                self.stack
+                    .push(self.stack[self.stack.len() - 1 - depth].clone());
+            }
+
+            IRKind::Roll { depth } => {
+                if self.stack.len() <= *depth {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Stack underflow".to_string(),
+                    });
+                }
+                let value = self.stack.remove(self.stack.len() - 1 - depth);
+                self.stack.push(value);
+            }
+
+            IRKind::StoreLocal { slot } => {
+                let value = pop_value(&mut self.stack, ir)?;
+                let frame = self.frames.last_mut().unwrap();
+                if frame.len() <= *slot {
+                    frame.resize(slot + 1, None);
+                }
+                frame[*slot] = Some(value);
+            }
+
+            IRKind::LoadLocal { slot } => {
+                let value = self
+                    .frames
+                    .last()
+                    .unwrap()
+                    .get(*slot)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| Error {
+                        location: ir.location.clone(),
+                        message: format!("Local slot {} has not been set", slot),
+                    })?;
+                self.stack.push(value);
+            }
+
+            IRKind::Load => {
+                let address = pop_integer(&mut self.stack, ir)?;
+                let mut bytes = [0; size_of::<i64>()];
+                bytes.copy_from_slice(
+                    &self.memory[get_memory_range(&self.memory, address, size_of::<i64>(), ir)?],
+                );
+                self.stack.push(Value::Integer(i64::from_le_bytes(bytes)));
+            }
+
+            IRKind::Store => {
+                let value = pop_integer(&mut self.stack, ir)?;
+                let address = pop_integer(&mut self.stack, ir)?;
+                let range = get_memory_range(&self.memory, address, size_of::<i64>(), ir)?;
+                self.memory[range].copy_from_slice(&value.to_le_bytes());
+            }
+
+            IRKind::Jump { relative_position } => {
+                self.ip = (position as isize + relative_position) as usize;
+            }
+
+            IRKind::JumpFalse { relative_position } => {
+                if !pop_bool(&mut self.stack, ir)? {
+                    self.ip = (position as isize + relative_position) as usize;
+                }
+            }
+
+            IRKind::JumpTrue { relative_position } => {
+                if pop_bool(&mut self.stack, ir)? {
+                    self.ip = (position as isize + relative_position) as usize;
+                }
+            }
+
+            IRKind::Call => {
+                let id = pop_procedure(&mut self.stack, ir)?;
+                if id >= procedures.len() {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: format!("Procedure {} does not exist", id),
+                    });
+                }
+                if self.return_stack.len() >= options.max_recursion_depth {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Maximum recursion depth exceeded".to_string(),
+                    });
+                }
+                self.return_stack.push((self.procedure, self.ip));
+                self.frames.push(Vec::new());
+                self.procedure = id;
+                self.ip = 0;
+            }
+
+            IRKind::TailCall => {
+                let id = pop_procedure(&mut self.stack, ir)?;
+                if id >= procedures.len() {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: format!("Procedure {} does not exist", id),
+                    });
+                }
+                self.frames.last_mut().unwrap().clear();
+                self.procedure = id;
+                self.ip = 0;
+            }
+
+            IRKind::Return => {
+                match self.return_stack.pop() {
+                    Some(return_position) => (self.procedure, self.ip) = return_position,
+                    None => {
+                        self.ip = position;
+                        return Ok(StepResult::Halted(0));
+                    }
+                }
+                self.frames.pop();
+            }
+
+            IRKind::Print => {
+                let a = pop_value(&mut self.stack, ir)?;
+                write_output(options, &format!("{}\n", a), ir)?;
+            }
+
+            IRKind::PrintHex => {
+                let a = pop_integer(&mut self.stack, ir)?;
+                let sign = if a < 0 { "-" } else { "" };
+                write_output(options, &format!("{}0x{:x}\n", sign, a.unsigned_abs()), ir)?;
+            }
+
+            IRKind::PrintBin => {
+                let a = pop_integer(&mut self.stack, ir)?;
+                let sign = if a < 0 { "-" } else { "" };
+                write_output(options, &format!("{}0b{:b}\n", sign, a.unsigned_abs()), ir)?;
+            }
+
+            IRKind::Emit => {
+                let a = pop_value(&mut self.stack, ir)?;
+                write_output(options, &a.to_string(), ir)?;
+            }
+
+            IRKind::Assert => {
+                if !pop_bool(&mut self.stack, ir)? {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Assertion failed".to_string(),
+                    });
+                }
+            }
+
+            IRKind::DumpStack => {
+                let mut text = format!("<{}>", self.stack.len());
+                for value in &self.stack {
+                    text.push_str(&format!(" {}", value));
+                }
+                text.push('\n');
+                write_output(options, &text, ir)?;
+            }
+
+            IRKind::ExitWithCode => {
+                let code = pop_integer(&mut self.stack, ir)?;
+                self.ip = position;
+                return Ok(StepResult::Halted(code));
+            }
+
+            IRKind::PrintStr => {
+                let length = pop_integer(&mut self.stack, ir)?;
+                let address = pop_integer(&mut self.stack, ir)?;
+                if length < 0 {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: format!("Invalid string length {}", length),
+                    });
+                }
+                let range = get_memory_range(&self.memory, address, length as usize, ir)?;
+                write_output(options, &String::from_utf8_lossy(&self.memory[range]), ir)?;
+            }
+        }
+
+        if self.ip >= procedures[self.procedure].len() {
+            return Err(Error {
+                location: ir.location.clone(),
+                message: format!(
+                    "Execution went outside of procedure {} at instruction {}",
+                    self.procedure, self.ip as isize
+                ),
+            });
+        }
+        Ok(StepResult::Continue)
+    }
+}
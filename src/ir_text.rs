@@ -0,0 +1,454 @@
+use std::fmt::Write;
+
+use crate::{
+    common::{Error, SourceLocation},
+    ir::{IRKind, IR},
+    types::Type,
+};
+
+fn format_kind(kind: &IRKind) -> String {
+    match kind {
+        IRKind::Exit => "exit".to_string(),
+
+        IRKind::PushProc { id } => format!("push_proc {}", id),
+        IRKind::PushInt { value } => format!("push_int {}", value),
+        IRKind::PushFloat { value } => format!("push_float {:?}", value),
+        IRKind::PushBool { value } => format!("push_bool {}", value),
+        IRKind::PushType { typ } => format!("push_type {}", typ),
+        IRKind::PushMemory { offset, size } => format!("push_memory {} {}", offset, size),
+        IRKind::PushConst { index } => format!("push_const {}", index),
+
+        IRKind::Add => "add".to_string(),
+        IRKind::Sub => "sub".to_string(),
+        IRKind::Mul => "mul".to_string(),
+        IRKind::Div => "div".to_string(),
+        IRKind::Mod => "mod".to_string(),
+        IRKind::BitAnd => "bit_and".to_string(),
+        IRKind::BitOr => "bit_or".to_string(),
+        IRKind::BitXor => "bit_xor".to_string(),
+        IRKind::ShiftLeft => "shift_left".to_string(),
+        IRKind::ShiftRight => "shift_right".to_string(),
+        IRKind::LessThan => "less_than".to_string(),
+        IRKind::GreaterThan => "greater_than".to_string(),
+        IRKind::LessThanEqual => "less_than_equal".to_string(),
+        IRKind::GreaterThanEqual => "greater_than_equal".to_string(),
+        IRKind::Equal => "equal".to_string(),
+        IRKind::NotEqual => "not_equal".to_string(),
+        IRKind::Not => "not".to_string(),
+        IRKind::Neg => "neg".to_string(),
+        IRKind::And => "and".to_string(),
+        IRKind::Or => "or".to_string(),
+        IRKind::Max => "max".to_string(),
+        IRKind::Min => "min".to_string(),
+
+        IRKind::Dup => "dup".to_string(),
+        IRKind::Drop => "drop".to_string(),
+        IRKind::Swap => "swap".to_string(),
+        IRKind::Over => "over".to_string(),
+        IRKind::Rot => "rot".to_string(),
+        IRKind::TwoDup => "two_dup".to_string(),
+        IRKind::Nip => "nip".to_string(),
+        IRKind::Tuck => "tuck".to_string(),
+        IRKind::DropN { count } => format!("drop_n {}", count),
+        IRKind::DupN { count } => format!("dup_n {}", count),
+        IRKind::Pick { depth } => format!("pick {}", depth),
+        IRKind::Roll { depth } => format!("roll {}", depth),
+
+        IRKind::Load => "load".to_string(),
+        IRKind::Store => "store".to_string(),
+        IRKind::StoreLocal { slot } => format!("store_local {}", slot),
+        IRKind::LoadLocal { slot } => format!("load_local {}", slot),
+
+        IRKind::Jump { relative_position } => format!("jump {:+}", relative_position),
+        IRKind::JumpFalse { relative_position } => {
+            format!("jump_false {:+}", relative_position)
+        }
+        IRKind::JumpTrue { relative_position } => format!("jump_true {:+}", relative_position),
+
+        IRKind::Call => "call".to_string(),
+        IRKind::TailCall => "tail_call".to_string(),
+        IRKind::Return => "return".to_string(),
+
+        IRKind::Print => "print".to_string(),
+        IRKind::PrintStr => "print_str".to_string(),
+        IRKind::PrintHex => "print_hex".to_string(),
+        IRKind::PrintBin => "print_bin".to_string(),
+        IRKind::Emit => "emit".to_string(),
+        IRKind::Assert => "assert".to_string(),
+        IRKind::ExitWithCode => "exit_with_code".to_string(),
+        IRKind::DumpStack => "dump_stack".to_string(),
+    }
+}
+
+/// Writes `procedures` in a textual form that can be read back with `parse`
+pub fn dump(procedures: &[Vec<IR>]) -> String {
+    let mut output = String::new();
+    for (id, procedure) in procedures.iter().enumerate() {
+        if id > 0 {
+            writeln!(output).unwrap();
+        }
+        writeln!(output, ".proc {}", id).unwrap();
+        for (position, ir) in procedure.iter().enumerate() {
+            writeln!(output, "{}: {}", position, format_kind(&ir.kind)).unwrap();
+        }
+    }
+    output
+}
+
+struct LineParser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl LineParser {
+    fn location(self: &LineParser) -> SourceLocation {
+        SourceLocation {
+            filepath: "<ir>".to_string(),
+            position: self.line_start + self.byte_offset(self.position),
+            line: self.line,
+            column: self.position + 1,
+            length: 0,
+        }
+    }
+
+    /// Converts a character index on the line into a byte offset from the start of the line
+    fn byte_offset(self: &LineParser, position: usize) -> usize {
+        self.chars[..position.min(self.chars.len())]
+            .iter()
+            .map(|chr| chr.len_utf8())
+            .sum()
+    }
+
+    fn error(self: &LineParser, message: String) -> Error {
+        Error {
+            location: self.location(),
+            message,
+        }
+    }
+
+    fn peek_char(self: &LineParser) -> char {
+        self.chars.get(self.position).copied().unwrap_or('\0')
+    }
+
+    fn skip_whitespace(self: &mut LineParser) {
+        while self.peek_char().is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn at_end(self: &mut LineParser) -> bool {
+        self.skip_whitespace();
+        self.position >= self.chars.len()
+    }
+
+    fn expect_char(self: &mut LineParser, expected: char) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.peek_char() != expected {
+            return Err(self.error(format!("Expected '{}'", expected)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_word(self: &mut LineParser) -> String {
+        self.skip_whitespace();
+        let mut word = String::new();
+        while let 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' | '.' = self.peek_char() {
+            word.push(self.peek_char());
+            self.position += 1;
+        }
+        word
+    }
+
+    fn parse_integer(self: &mut LineParser) -> Result<i128, Error> {
+        self.skip_whitespace();
+        let start = self.position;
+        if let '+' | '-' = self.peek_char() {
+            self.position += 1;
+        }
+        while self.peek_char().is_ascii_digit() {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().map_err(|_| Error {
+            location: SourceLocation {
+                column: start + 1,
+                position: self.line_start + self.byte_offset(start),
+                ..self.location()
+            },
+            message: format!("Expected an integer, but got '{}'", text),
+        })
+    }
+
+    fn parse_isize(self: &mut LineParser) -> Result<isize, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        let value = self.parse_integer()?;
+        isize::try_from(value).map_err(|_| Error {
+            location,
+            message: format!("Integer {} is too large", value),
+        })
+    }
+
+    fn parse_i64(self: &mut LineParser) -> Result<i64, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        let value = self.parse_integer()?;
+        i64::try_from(value).map_err(|_| Error {
+            location,
+            message: format!("Integer {} is too large", value),
+        })
+    }
+
+    fn parse_usize(self: &mut LineParser) -> Result<usize, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        let value = self.parse_integer()?;
+        usize::try_from(value).map_err(|_| Error {
+            location,
+            message: format!("Expected a positive integer, but got {}", value),
+        })
+    }
+
+    fn parse_float(self: &mut LineParser) -> Result<f64, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        let mut text = String::new();
+        while !self.peek_char().is_whitespace() && self.peek_char() != '\0' {
+            text.push(self.peek_char());
+            self.position += 1;
+        }
+        text.parse().map_err(|_| Error {
+            location,
+            message: format!("Expected a float, but got '{}'", text),
+        })
+    }
+
+    fn parse_types_until(self: &mut LineParser, end: char) -> Result<Vec<Type>, Error> {
+        let mut types = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.peek_char() == end {
+                self.position += 1;
+                return Ok(types);
+            }
+            types.push(self.parse_type()?);
+        }
+    }
+
+    fn parse_type(self: &mut LineParser) -> Result<Type, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        match &self.parse_word() as &str {
+            "type" => Ok(Type::Type),
+            "int" => Ok(Type::Integer),
+            "float" => Ok(Type::Float),
+            "bool" => Ok(Type::Bool),
+            "proc" => {
+                self.expect_char('(')?;
+                let parameters = self.parse_types_until(')')?;
+                self.expect_char('-')?;
+                self.expect_char('>')?;
+                self.expect_char('(')?;
+                let return_types = self.parse_types_until(')')?;
+                Ok(Type::Procedure {
+                    parameters,
+                    return_types,
+                })
+            }
+            word => Err(Error {
+                location,
+                message: format!("Unknown type '{}'", word),
+            }),
+        }
+    }
+
+    fn parse_kind(self: &mut LineParser) -> Result<IRKind, Error> {
+        self.skip_whitespace();
+        let location = self.location();
+        Ok(match &self.parse_word() as &str {
+            "exit" => IRKind::Exit,
+
+            "push_proc" => IRKind::PushProc {
+                id: self.parse_usize()?,
+            },
+            "push_int" => IRKind::PushInt {
+                value: self.parse_i64()?,
+            },
+            "push_float" => IRKind::PushFloat {
+                value: self.parse_float()?,
+            },
+            "push_bool" => {
+                self.skip_whitespace();
+                let location = self.location();
+                match &self.parse_word() as &str {
+                    "true" => IRKind::PushBool { value: true },
+                    "false" => IRKind::PushBool { value: false },
+                    word => {
+                        return Err(Error {
+                            location,
+                            message: format!("Expected a bool, but got '{}'", word),
+                        })
+                    }
+                }
+            }
+            "push_type" => IRKind::PushType {
+                typ: self.parse_type()?,
+            },
+            "push_memory" => IRKind::PushMemory {
+                offset: self.parse_usize()?,
+                size: self.parse_usize()?,
+            },
+            "push_const" => IRKind::PushConst {
+                index: self.parse_usize()?,
+            },
+
+            "add" => IRKind::Add,
+            "sub" => IRKind::Sub,
+            "mul" => IRKind::Mul,
+            "div" => IRKind::Div,
+            "mod" => IRKind::Mod,
+            "bit_and" => IRKind::BitAnd,
+            "bit_or" => IRKind::BitOr,
+            "bit_xor" => IRKind::BitXor,
+            "shift_left" => IRKind::ShiftLeft,
+            "shift_right" => IRKind::ShiftRight,
+            "less_than" => IRKind::LessThan,
+            "greater_than" => IRKind::GreaterThan,
+            "less_than_equal" => IRKind::LessThanEqual,
+            "greater_than_equal" => IRKind::GreaterThanEqual,
+            "equal" => IRKind::Equal,
+            "not_equal" => IRKind::NotEqual,
+            "not" => IRKind::Not,
+            "neg" => IRKind::Neg,
+            "and" => IRKind::And,
+            "or" => IRKind::Or,
+            "max" => IRKind::Max,
+            "min" => IRKind::Min,
+
+            "dup" => IRKind::Dup,
+            "drop" => IRKind::Drop,
+            "swap" => IRKind::Swap,
+            "over" => IRKind::Over,
+            "rot" => IRKind::Rot,
+            "two_dup" => IRKind::TwoDup,
+            "nip" => IRKind::Nip,
+            "tuck" => IRKind::Tuck,
+            "drop_n" => IRKind::DropN {
+                count: self.parse_usize()?,
+            },
+            "dup_n" => IRKind::DupN {
+                count: self.parse_usize()?,
+            },
+            "pick" => IRKind::Pick {
+                depth: self.parse_usize()?,
+            },
+            "roll" => IRKind::Roll {
+                depth: self.parse_usize()?,
+            },
+
+            "load" => IRKind::Load,
+            "store" => IRKind::Store,
+            "store_local" => IRKind::StoreLocal {
+                slot: self.parse_usize()?,
+            },
+            "load_local" => IRKind::LoadLocal {
+                slot: self.parse_usize()?,
+            },
+
+            "jump" => IRKind::Jump {
+                relative_position: self.parse_isize()?,
+            },
+            "jump_false" => IRKind::JumpFalse {
+                relative_position: self.parse_isize()?,
+            },
+            "jump_true" => IRKind::JumpTrue {
+                relative_position: self.parse_isize()?,
+            },
+
+            "call" => IRKind::Call,
+            "tail_call" => IRKind::TailCall,
+            "return" => IRKind::Return,
+
+            "print" => IRKind::Print,
+            "print_str" => IRKind::PrintStr,
+            "print_hex" => IRKind::PrintHex,
+            "print_bin" => IRKind::PrintBin,
+            "emit" => IRKind::Emit,
+            "assert" => IRKind::Assert,
+            "exit_with_code" => IRKind::ExitWithCode,
+            "dump_stack" => IRKind::DumpStack,
+
+            mnemonic => {
+                return Err(Error {
+                    location,
+                    message: format!("Unknown mnemonic '{}'", mnemonic),
+                })
+            }
+        })
+    }
+}
+
+/// Reads procedures written by `dump`, the `N:` instruction index prefixes are optional
+pub fn parse(text: &str) -> Result<Vec<Vec<IR>>, Error> {
+    let mut procedures: Vec<Vec<IR>> = Vec::new();
+
+    let mut line_start = 0;
+    for (line_index, full_line) in text.split_inclusive('\n').enumerate() {
+        let line = full_line.trim_end_matches('\n').trim_end_matches('\r');
+        let mut parser = LineParser {
+            chars: line.chars().collect(),
+            position: 0,
+            line: line_index + 1,
+            line_start,
+        };
+        line_start += full_line.len();
+
+        if parser.at_end() {
+            continue;
+        }
+
+        if parser.peek_char() == '.' {
+            let location = parser.location();
+            let directive = parser.parse_word();
+            if directive != ".proc" {
+                return Err(Error {
+                    location,
+                    message: format!("Unknown directive '{}'", directive),
+                });
+            }
+            parser.skip_whitespace();
+            let location = parser.location();
+            let id = parser.parse_usize()?;
+            if id != procedures.len() {
+                return Err(Error {
+                    location,
+                    message: format!("Expected procedure {}, but got {}", procedures.len(), id),
+                });
+            }
+            procedures.push(Vec::new());
+        } else {
+            if parser.peek_char().is_ascii_digit() {
+                parser.parse_usize()?;
+                parser.expect_char(':')?;
+            }
+
+            parser.skip_whitespace();
+            let location = parser.location();
+            let kind = parser.parse_kind()?;
+            let procedure = procedures.last_mut().ok_or_else(|| Error {
+                location: location.clone(),
+                message: "Expected '.proc' before the first instruction".to_string(),
+            })?;
+            procedure.push(IR { location, kind });
+        }
+
+        if !parser.at_end() {
+            return Err(parser.error("Unexpected text at the end of the line".to_string()));
+        }
+    }
+
+    Ok(procedures)
+}
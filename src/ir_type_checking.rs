@@ -0,0 +1,526 @@
+use crate::{
+    common::Error,
+    ir::{IRKind, IR},
+    types::{format_types, Type},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Context {
+    position: usize,
+    stack: Vec<Type>,
+    locals: Vec<Option<Type>>,
+    /// The instruction this path ran just before reaching `position`
+    previous: Option<usize>,
+}
+
+/// Keeps only the local types both paths agree on, a local missing from either may not have been set
+fn merge_locals(a: &[Option<Type>], b: &[Option<Type>]) -> Vec<Option<Type>> {
+    (0..a.len().max(b.len()))
+        .map(|slot| match (a.get(slot), b.get(slot)) {
+            (Some(Some(a)), Some(Some(b))) if a == b => Some(a.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn pop_type(stack: &mut Vec<Type>, ir: &IR) -> Result<Type, Error> {
+    stack.pop().ok_or_else(|| Error {
+        location: ir.location.clone(),
+        message: "Expected a value on the stack".to_string(),
+    })
+}
+
+fn expect_type(stack: &mut Vec<Type>, expected: &Type, ir: &IR) -> Result<(), Error> {
+    let actual = pop_type(stack, ir)?;
+    if &actual != expected {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected type '{}', but got type '{}'", expected, actual),
+        });
+    }
+    Ok(())
+}
+
+/// Pops two operands which must both be ints or both be floats, returning their type
+fn expect_numbers(stack: &mut Vec<Type>, ir: &IR) -> Result<Type, Error> {
+    let typ = pop_type(stack, ir)?;
+    if typ != Type::Integer && typ != Type::Float {
+        return Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected type 'int' or 'float', but got type '{}'", typ),
+        });
+    }
+    expect_type(stack, &typ, ir)?;
+    Ok(typ)
+}
+
+pub fn type_check_ir(procedures: &[Vec<IR>], signatures: &[Type]) -> Result<(), Error> {
+    for id in 0..procedures.len() {
+        type_check_procedure(procedures, signatures, id)?;
+    }
+    Ok(())
+}
+
+fn type_check_procedure(
+    procedures: &[Vec<IR>],
+    signatures: &[Type],
+    id: usize,
+) -> Result<(), Error> {
+    let (parameters, return_types) = match signatures[id].clone() {
+        Type::Procedure {
+            parameters,
+            return_types,
+        } => (parameters, return_types),
+        _ => unreachable!(),
+    };
+    check_code(
+        &procedures[id],
+        signatures,
+        0,
+        parameters,
+        &return_types,
+        false,
+    )?;
+    Ok(())
+}
+
+/// Gets the types on the stack at the end of `procedure`, which is still being compiled,
+/// when it starts at `start_position` with `parameters` on the stack.
+/// Jumps that have not been patched yet are expected to have a relative position of 0 and are not followed.
+/// Returns `None` if the end can not be reached, like after a `return`.
+pub fn get_stack_at_end(
+    procedure: &[IR],
+    signatures: &[Type],
+    start_position: usize,
+    parameters: Vec<Type>,
+) -> Result<Option<Vec<Type>>, Error> {
+    check_code(procedure, signatures, start_position, parameters, &[], true)
+}
+
+/// Checks the code reachable from `start_position`, when `partial` is true the procedure is still being compiled
+/// and the stack at its end is returned instead of reaching the end being an error
+fn check_code(
+    procedure: &[IR],
+    signatures: &[Type],
+    start_position: usize,
+    parameters: Vec<Type>,
+    return_types: &[Type],
+    partial: bool,
+) -> Result<Option<Vec<Type>>, Error> {
+    let mut visited: Vec<Option<Context>> = vec![None; procedure.len()];
+    let mut contexts = vec![Context {
+        position: start_position,
+        stack: parameters,
+        locals: Vec::new(),
+        previous: None,
+    }];
+    let mut end_stack: Option<Vec<Type>> = None;
+
+    while let Some(mut context) = contexts.pop() {
+        loop {
+            if partial && context.position == procedure.len() {
+                if let Some(end_stack) = &end_stack {
+                    if end_stack != &context.stack {
+                        return Err(Error {
+                            location: procedure.last().unwrap().location.clone(),
+                            message: format!(
+                                "Stack mismatch, expected ({}), but got ({})",
+                                format_types(end_stack),
+                                format_types(&context.stack),
+                            ),
+                        });
+                    }
+                }
+                end_stack = Some(context.stack);
+                break;
+            }
+
+            let ir = &procedure[context.position];
+
+            // checked before the visited check, a path from a jump could have reached the operator first
+            if matches!(ir.kind, IRKind::Div | IRKind::Mod)
+                && context.position > 0
+                && context.previous == Some(context.position - 1)
+                && procedure[context.position - 1].kind == (IRKind::PushInt { value: 0 })
+            {
+                return Err(Error {
+                    location: ir.location.clone(),
+                    message: "Division by zero".to_string(),
+                });
+            }
+
+            if let Some(visited_context) = &visited[context.position] {
+                // every path reaching a return has to match the return types, not just the first one
+                if !partial && matches!(ir.kind, IRKind::Return) {
+                    check_return(return_types, procedure, &context)?;
+                }
+                let stack = &visited_context.stack;
+                if stack != &context.stack {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: format!(
+                            "Stack mismatch, expected ({}), but got ({})",
+                            format_types(stack),
+                            format_types(&context.stack),
+                        ),
+                    });
+                }
+                // only check the rest again if this path knows less about the locals than before
+                let locals = merge_locals(&visited_context.locals, &context.locals);
+                if locals == visited_context.locals {
+                    break;
+                }
+                context.locals = locals;
+            }
+            visited[context.position] = Some(context.clone());
+
+            let stack = &mut context.stack;
+            let locals = &mut context.locals;
+            match &ir.kind {
+                IRKind::Exit => break,
+
+                IRKind::PushProc { id } => stack.push(signatures[*id].clone()),
+                IRKind::PushInt { value: _ } => stack.push(Type::Integer),
+                IRKind::PushFloat { value: _ } => stack.push(Type::Float),
+                IRKind::PushBool { value: _ } => stack.push(Type::Bool),
+                IRKind::PushType { typ: _ } => stack.push(Type::Type),
+                IRKind::PushMemory { offset: _, size: _ } => stack.push(Type::Integer),
+                IRKind::PushConst { index: _ } => {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: "Pooled constants can not be type checked, constants should be pooled after type checking".to_string(),
+                    })
+                }
+
+                IRKind::Add | IRKind::Sub | IRKind::Mul | IRKind::Div => {
+                    let typ = expect_numbers(stack, ir)?;
+                    stack.push(typ);
+                }
+
+                IRKind::Mod
+                | IRKind::Max
+                | IRKind::Min
+                | IRKind::BitAnd
+                | IRKind::BitOr
+                | IRKind::BitXor
+                | IRKind::ShiftLeft
+                | IRKind::ShiftRight => {
+                    expect_type(stack, &Type::Integer, ir)?;
+                    expect_type(stack, &Type::Integer, ir)?;
+                    stack.push(Type::Integer);
+                }
+
+                IRKind::LessThan
+                | IRKind::GreaterThan
+                | IRKind::LessThanEqual
+                | IRKind::GreaterThanEqual => {
+                    expect_numbers(stack, ir)?;
+                    stack.push(Type::Bool);
+                }
+
+                IRKind::Equal | IRKind::NotEqual => {
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    if a != b {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!(
+                                "Cannot compare values of different types '{}' and '{}'",
+                                a, b
+                            ),
+                        });
+                    }
+                    stack.push(Type::Bool);
+                }
+
+                IRKind::Not => {
+                    expect_type(stack, &Type::Bool, ir)?;
+                    stack.push(Type::Bool);
+                }
+
+                IRKind::And | IRKind::Or => {
+                    expect_type(stack, &Type::Bool, ir)?;
+                    expect_type(stack, &Type::Bool, ir)?;
+                    stack.push(Type::Bool);
+                }
+
+                IRKind::Neg => {
+                    let typ = pop_type(stack, ir)?;
+                    if typ != Type::Integer && typ != Type::Float {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!(
+                                "Expected type 'int' or 'float', but got type '{}'",
+                                typ
+                            ),
+                        });
+                    }
+                    stack.push(typ);
+                }
+
+                IRKind::Dup => {
+                    let typ = pop_type(stack, ir)?;
+                    stack.push(typ.clone());
+                    stack.push(typ);
+                }
+
+                IRKind::Drop => {
+                    pop_type(stack, ir)?;
+                }
+
+                IRKind::Swap => {
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    stack.push(b);
+                    stack.push(a);
+                }
+
+                IRKind::Over => {
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    stack.push(a.clone());
+                    stack.push(b);
+                    stack.push(a);
+                }
+
+                IRKind::Rot => {
+                    let c = pop_type(stack, ir)?;
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    stack.push(b);
+                    stack.push(c);
+                    stack.push(a);
+                }
+
+                IRKind::TwoDup => {
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    stack.push(a.clone());
+                    stack.push(b.clone());
+                    stack.push(a);
+                    stack.push(b);
+                }
+
+                IRKind::Nip => {
+                    let b = pop_type(stack, ir)?;
+                    pop_type(stack, ir)?;
+                    stack.push(b);
+                }
+
+                IRKind::Tuck => {
+                    let b = pop_type(stack, ir)?;
+                    let a = pop_type(stack, ir)?;
+                    stack.push(b.clone());
+                    stack.push(a);
+                    stack.push(b);
+                }
+
+                IRKind::DropN { count } => {
+                    for _ in 0..*count {
+                        pop_type(stack, ir)?;
+                    }
+                }
+
+                IRKind::DupN { count } => {
+                    if stack.len() < *count {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: "Expected a value on the stack".to_string(),
+                        });
+                    }
+                    stack.extend_from_within(stack.len() - count..);
+                }
+
+                IRKind::Pick { depth } | IRKind::Roll { depth } => {
+                    if stack.len() <= *depth {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!(
+                                "Expected at least {} values on the stack, but got {}",
+                                depth + 1,
+                                stack.len()
+                            ),
+                        });
+                    }
+                    let index = stack.len() - 1 - depth;
+                    let typ = if let IRKind::Roll { depth: _ } = ir.kind {
+                        stack.remove(index)
+                    } else {
+                        stack[index].clone()
+                    };
+                    stack.push(typ);
+                }
+
+                IRKind::Load => {
+                    expect_type(stack, &Type::Integer, ir)?;
+                    stack.push(Type::Integer);
+                }
+
+                IRKind::Store => {
+                    expect_type(stack, &Type::Integer, ir)?;
+                    expect_type(stack, &Type::Integer, ir)?;
+                }
+
+                IRKind::StoreLocal { slot } => {
+                    let typ = pop_type(stack, ir)?;
+                    if locals.len() <= *slot {
+                        locals.resize(slot + 1, None);
+                    }
+                    locals[*slot] = Some(typ);
+                }
+
+                IRKind::LoadLocal { slot } => match locals.get(*slot) {
+                    Some(Some(typ)) => stack.push(typ.clone()),
+                    _ => {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!("Local slot {} may not have been set", slot),
+                        })
+                    }
+                },
+
+                IRKind::Jump { relative_position: 0 } if partial => break,
+
+                IRKind::Jump { relative_position } => {
+                    context.previous = Some(context.position);
+                    context.position =
+                        jump_target(procedure, context.position, *relative_position, partial)?;
+                    continue;
+                }
+
+                IRKind::JumpFalse { relative_position } | IRKind::JumpTrue { relative_position } => {
+                    expect_type(stack, &Type::Bool, ir)?;
+                    if !(partial && *relative_position == 0) {
+                        contexts.push(Context {
+                            position: jump_target(
+                                procedure,
+                                context.position,
+                                *relative_position,
+                                partial,
+                            )?,
+                            stack: stack.clone(),
+                            locals: locals.clone(),
+                            previous: Some(context.position),
+                        });
+                    }
+                }
+
+                IRKind::Call => call_procedure(stack, ir)?,
+
+                IRKind::TailCall => {
+                    call_procedure(stack, ir)?;
+                    if !partial && stack != return_types {
+                        return Err(return_mismatch(stack, return_types, ir));
+                    }
+                    break;
+                }
+
+                IRKind::Return => {
+                    if !partial {
+                        check_return(return_types, procedure, &context)?;
+                    }
+                    break;
+                }
+
+                IRKind::Print | IRKind::Emit => {
+                    pop_type(stack, ir)?;
+                }
+
+                IRKind::PrintHex | IRKind::PrintBin => expect_type(stack, &Type::Integer, ir)?,
+
+                IRKind::PrintStr => {
+                    expect_type(stack, &Type::Integer, ir)?;
+                    expect_type(stack, &Type::Integer, ir)?;
+                }
+
+                IRKind::Assert => expect_type(stack, &Type::Bool, ir)?,
+
+                IRKind::DumpStack => {}
+
+                IRKind::ExitWithCode => {
+                    expect_type(stack, &Type::Integer, ir)?;
+                    break;
+                }
+            }
+
+            context.previous = Some(context.position);
+            context.position += 1;
+            if !partial && context.position >= procedure.len() {
+                return Err(Error {
+                    location: ir.location.clone(),
+                    message: "Reached the end of the procedure without a return".to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(end_stack)
+}
+
+/// Applies the stack effect of the procedure type on top of the stack.
+/// Only the declared signature is used, so recursive calls never need the callee's body to be checked first.
+fn call_procedure(stack: &mut Vec<Type>, ir: &IR) -> Result<(), Error> {
+    match pop_type(stack, ir)? {
+        Type::Procedure {
+            parameters,
+            return_types,
+        } => {
+            for parameter in parameters.iter().rev() {
+                expect_type(stack, parameter, ir)?;
+            }
+            stack.extend(return_types);
+            Ok(())
+        }
+
+        typ => Err(Error {
+            location: ir.location.clone(),
+            message: format!("Expected a procedure, but got type '{}'", typ),
+        }),
+    }
+}
+
+/// Checks the stack of a path reaching a return, the error points at the last instruction on that path
+/// so that when only one branch is wrong it is clear which one
+fn check_return(return_types: &[Type], procedure: &[IR], context: &Context) -> Result<(), Error> {
+    let stack = &context.stack;
+    if stack != return_types {
+        let ir = context
+            .previous
+            .map_or(&procedure[context.position], |previous| {
+                &procedure[previous]
+            });
+        return Err(return_mismatch(stack, return_types, ir));
+    }
+    Ok(())
+}
+
+fn return_mismatch(stack: &[Type], return_types: &[Type], ir: &IR) -> Error {
+    Error {
+        location: ir.location.clone(),
+        message: format!(
+            "Expected ({}) on the stack at the end of the procedure, but got ({})",
+            format_types(return_types),
+            format_types(stack),
+        ),
+    }
+}
+
+/// Gets where a jump lands, `allow_end` lets it land just past the last instruction
+fn jump_target(
+    procedure: &[IR],
+    position: usize,
+    relative_position: isize,
+    allow_end: bool,
+) -> Result<usize, Error> {
+    let target = position as isize + relative_position;
+    if target < 0
+        || target as usize > procedure.len()
+        || (!allow_end && target as usize == procedure.len())
+    {
+        return Err(Error {
+            location: procedure[position].location.clone(),
+            message: format!("Jump target {} is outside of the procedure", target),
+        });
+    }
+    Ok(target as usize)
+}
@@ -0,0 +1,104 @@
+use crate::{
+    common::{Error, SourceLocation},
+    ir::{IRKind, IR},
+};
+
+/// Finds a location to report a problem with the procedure `id` that has no instructions,
+/// this is the first place the procedure is pushed if there is one
+fn empty_procedure_location(procedures: &[Vec<IR>], id: usize) -> SourceLocation {
+    procedures
+        .iter()
+        .flatten()
+        .find(|ir| ir.kind == IRKind::PushProc { id })
+        .map(|ir| ir.location.clone())
+        .unwrap_or_else(|| SourceLocation {
+            filepath: "<ir>".to_string(),
+            position: 0,
+            line: 1,
+            column: 1,
+            length: 0,
+        })
+}
+
+/// Gets whether execution can never run past the last instruction of `procedure`,
+/// procedure 0 has nothing to return to so it can not end with a `Return` or `TailCall`.
+/// Dead code elimination removes the final `Exit` or `Return` when it can not be reached,
+/// such as after an `ExitWithCode` or an infinite loop
+fn ends_with_terminator(id: usize, procedure: &[IR]) -> bool {
+    let Some(ir) = procedure.last() else {
+        return false;
+    };
+    match ir.kind {
+        IRKind::Exit | IRKind::ExitWithCode => true,
+        IRKind::Return | IRKind::TailCall => id != 0,
+        IRKind::Jump { relative_position } => relative_position <= 0,
+        _ => false,
+    }
+}
+
+/// Checks that `procedures` is structurally sound before it gets executed:
+/// every jump lands inside its procedure, every pushed procedure exists,
+/// and every procedure ends with an instruction that does not fall through, like a `Return`, or an `Exit` for procedure 0
+pub fn verify(procedures: &[Vec<IR>]) -> Result<(), Error> {
+    if procedures.is_empty() {
+        return Err(Error {
+            location: empty_procedure_location(procedures, 0),
+            message: "Expected at least one procedure".to_string(),
+        });
+    }
+
+    for (id, procedure) in procedures.iter().enumerate() {
+        for (position, ir) in procedure.iter().enumerate() {
+            match ir.kind {
+                IRKind::Jump { relative_position }
+                | IRKind::JumpFalse { relative_position }
+                | IRKind::JumpTrue { relative_position } => {
+                    let target = position as isize + relative_position;
+                    if target < 0 || target > procedure.len() as isize {
+                        return Err(Error {
+                            location: ir.location.clone(),
+                            message: format!(
+                                "Jump target {} is outside of procedure {} with {} instructions",
+                                target,
+                                id,
+                                procedure.len()
+                            ),
+                        });
+                    }
+                }
+
+                IRKind::PushProc { id: pushed_id } if pushed_id >= procedures.len() => {
+                    return Err(Error {
+                        location: ir.location.clone(),
+                        message: format!("Procedure {} does not exist", pushed_id),
+                    });
+                }
+
+                _ => {}
+            }
+        }
+
+        match procedure.last() {
+            Some(_) if ends_with_terminator(id, procedure) => {}
+            Some(ir) => {
+                let expected_kind = if id == 0 {
+                    IRKind::Exit
+                } else {
+                    IRKind::Return
+                };
+                return Err(Error {
+                    location: ir.location.clone(),
+                    message: format!("Expected procedure {} to end with {:?}", id, expected_kind),
+                });
+            }
+            None => {
+                return Err(Error {
+                    location: empty_procedure_location(procedures, id),
+                    message: format!("Procedure {} has no instructions", id),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::{array::IntoIter, collections::HashMap};
+use std::{cell::RefCell, collections::HashMap};
 
 use crate::{
     common::{Error, SourceLocation},
@@ -6,109 +6,232 @@ use crate::{
     tokenizer::Tokenizer,
 };
 
+/// Reads tokens straight out of the borrowed source, positions in its locations are byte offsets
 #[derive(Debug, Clone)]
-pub struct Lexer {
-    source: Vec<char>,
+pub struct Lexer<'a> {
+    source: &'a str,
     location: SourceLocation,
+    tab_width: usize,
+    /// The result of lexing the next token and the location after it, filled in by `peek_token`
+    peeked: RefCell<Option<(Result<Token, Error>, SourceLocation)>>,
 }
 
 lazy_static::lazy_static! {
-    static ref LEXER_SINGLE_CHARS: HashMap<char, TokenKind> =
-        HashMap::from_iter(IntoIter::new([
-            ('(', TokenKind::OpenParenthesis),
-            (')', TokenKind::CloseParenthesis),
-            ('{', TokenKind::OpenBrace),
-            ('}', TokenKind::CloseBrace),
-
-            ('!', TokenKind::Not),
-
-            ('+', TokenKind::Plus),
-            ('-', TokenKind::Minus),
-            ('*', TokenKind::Asterisk),
-            ('/', TokenKind::Slash),
-
-            ('=', TokenKind::Equal),
-
-            ('<', TokenKind::LessThan),
-            ('>', TokenKind::GreaterThan),
-        ]));
-
-    static ref LEXER_DOUBLE_CHARS: HashMap<char, HashMap<char, TokenKind>> =
-        HashMap::from_iter(IntoIter::new([
-            ('=',  HashMap::from_iter(IntoIter::new([('=', TokenKind::EqualEqual)]))),
-
-            ('<',  HashMap::from_iter(IntoIter::new([('=', TokenKind::LessThanEqual)]))),
-            ('>',  HashMap::from_iter(IntoIter::new([('=', TokenKind::GreaterThanEqual)]))),
-
-            ('-',  HashMap::from_iter(IntoIter::new([('>', TokenKind::RightArrow)]))),
-
-            ('!',  HashMap::from_iter(IntoIter::new([('=', TokenKind::NotEqual)]))),
-        ]));
+    /// Sorted longest first, so the first operator that matches is the longest match
+    static ref LEXER_OPERATORS: Vec<(&'static str, TokenKind)> = {
+        let mut operators = vec![
+            ("(", TokenKind::OpenParenthesis),
+            (")", TokenKind::CloseParenthesis),
+            ("{", TokenKind::OpenBrace),
+            ("}", TokenKind::CloseBrace),
+
+            ("!", TokenKind::Not),
+            ("@", TokenKind::At),
+
+            ("+", TokenKind::Plus),
+            ("-", TokenKind::Minus),
+            ("*", TokenKind::Asterisk),
+            ("/", TokenKind::Slash),
+            ("%", TokenKind::Percent),
+
+            ("&", TokenKind::Ampersand),
+            ("|", TokenKind::Pipe),
+            ("^", TokenKind::Caret),
+
+            ("=", TokenKind::Equal),
+            ("==", TokenKind::EqualEqual),
+            ("!=", TokenKind::NotEqual),
+
+            ("<", TokenKind::LessThan),
+            (">", TokenKind::GreaterThan),
+            ("<=", TokenKind::LessThanEqual),
+            (">=", TokenKind::GreaterThanEqual),
+            ("<<", TokenKind::ShiftLeft),
+            (">>", TokenKind::ShiftRight),
+
+            ("->", TokenKind::RightArrow),
+            ("<-", TokenKind::LeftArrow),
+        ];
+        operators.sort_by_key(|(operator, _)| std::cmp::Reverse(operator.len()));
+        operators
+    };
 
     static ref LEXER_KEYWORDS: HashMap<&'static str, TokenKind> =
-        HashMap::from_iter(IntoIter::new([
+        HashMap::from([
+            ("true", TokenKind::True),
+            ("false", TokenKind::False),
+
             ("print", TokenKind::Print),
+            ("print_str", TokenKind::PrintStr),
+            ("print_hex", TokenKind::PrintHex),
+            ("print_bin", TokenKind::PrintBin),
+            ("emit", TokenKind::Emit),
+            ("assert", TokenKind::Assert),
+            ("exit", TokenKind::Exit),
+            (".s", TokenKind::DumpStack),
+            ("typeof", TokenKind::TypeOf),
 
             ("if", TokenKind::If),
             ("else", TokenKind::Else),
+            ("elif", TokenKind::Elif),
+            ("include", TokenKind::Include),
 
             ("while", TokenKind::While),
 
             ("const", TokenKind::Const),
+            ("alias", TokenKind::Alias),
+            ("let", TokenKind::Let),
+
+            ("neg", TokenKind::Neg),
+            ("and", TokenKind::And),
+            ("or", TokenKind::Or),
+            ("max", TokenKind::Max),
+            ("min", TokenKind::Min),
 
             ("dup", TokenKind::Dup),
             ("drop", TokenKind::Drop),
             ("swap", TokenKind::Swap),
+            ("over", TokenKind::Over),
+            ("rot", TokenKind::Rot),
+            ("2dup", TokenKind::TwoDup),
+            ("nip", TokenKind::Nip),
+            ("tuck", TokenKind::Tuck),
+            ("dropn", TokenKind::DropN),
+            ("dupn", TokenKind::DupN),
+            ("pick", TokenKind::Pick),
+            ("roll", TokenKind::Roll),
 
             ("memory", TokenKind::Memory),
+            ("load", TokenKind::Load),
+            ("store", TokenKind::Store),
 
             ("proc", TokenKind::Proc),
             ("call", TokenKind::Call),
-        ]));
+        ]);
 }
 
-impl Lexer {
-    pub fn new(filepath: String, source: &str) -> Lexer {
+impl<'a> Lexer<'a> {
+    pub fn new(filepath: String, source: &'a str) -> Lexer<'a> {
+        Lexer::with_tab_width(filepath, source, 1)
+    }
+
+    /// Creates a lexer where a tab advances the column to the next multiple of `tab_width`
+    pub fn with_tab_width(filepath: String, source: &'a str, tab_width: usize) -> Lexer<'a> {
         Lexer {
-            source: source.chars().into_iter().collect(),
+            source,
             location: SourceLocation {
                 filepath,
                 position: 0,
                 line: 1,
                 column: 1,
+                length: 0,
             },
+            tab_width: tab_width.max(1),
+            peeked: RefCell::new(None),
+        }
+    }
+
+    /// Gets `start_location` extended to cover the source up to the current location
+    fn get_location_since(self: &Lexer<'a>, start_location: &SourceLocation) -> SourceLocation {
+        SourceLocation {
+            length: self.location.position - start_location.position,
+            ..start_location.clone()
+        }
+    }
+
+    fn peek_char(self: &Lexer<'a>) -> char {
+        self.peek_char_at(0)
+    }
+
+    /// Gets the character `offset` characters ahead, the offset is counted in characters not bytes
+    fn peek_char_at(self: &Lexer<'a>, offset: usize) -> char {
+        // most source is ASCII, where a character is a single byte and no decoding is needed
+        let bytes = self.source.as_bytes();
+        let position = self.location.position + offset;
+        if position < bytes.len()
+            && bytes[position].is_ascii()
+            && (offset == 0 || bytes[self.location.position..position].is_ascii())
+        {
+            return bytes[position] as char;
         }
+        self.source[self.location.position..]
+            .chars()
+            .nth(offset)
+            .unwrap_or('\0')
     }
 
-    fn peek_char(self: &Lexer) -> char {
-        if self.location.position < self.source.len() {
-            self.source[self.location.position]
-        } else {
-            '\0'
+    /// Checks the `_` about to be skipped in a number is between two digits
+    fn check_digit_separator(self: &Lexer<'a>, after_digit: bool) -> Result<(), Error> {
+        if !after_digit || !self.peek_char_at(1).is_ascii_alphanumeric() {
+            return Err(Error {
+                location: self.location.clone(),
+                message: "'_' in a number must be between two digits".to_string(),
+            });
         }
+        Ok(())
     }
 
-    fn next_char(self: &mut Lexer) -> char {
+    fn next_char(self: &mut Lexer<'a>) -> char {
         let chr = self.peek_char();
-        self.location.position += 1;
-        self.location.column += 1;
-        if chr == '\n' {
-            self.location.line += 1;
-            self.location.column = 1;
+        self.location.position = (self.location.position + chr.len_utf8()).min(self.source.len());
+        match chr {
+            '\n' => {
+                self.location.line += 1;
+                self.location.column = 1;
+            }
+            // part of a \r\n line ending, so it does not take up a column
+            '\r' => {}
+            '\t' => {
+                self.location.column = (self.location.column - 1) / self.tab_width * self.tab_width
+                    + self.tab_width
+                    + 1;
+            }
+            _ => self.location.column += 1,
+        }
+        chr
+    }
+
+    fn peek_name(self: &Lexer<'a>) -> String {
+        let mut name = String::new();
+        if self.peek_char() == '.' {
+            name.push('.');
+        }
+        while let 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' = self.peek_char_at(name.len()) {
+            name.push(self.peek_char_at(name.len()));
+        }
+        name
+    }
+
+    fn next_escaped_char(self: &mut Lexer<'a>) -> Result<char, Error> {
+        let escape_location = self.location.clone();
+        let chr = self.next_char();
+        if chr != '\\' {
+            return Ok(chr);
+        }
+        match self.next_char() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '\'' => Ok('\''),
+            '"' => Ok('"'),
+            chr => Err(Error {
+                location: escape_location,
+                message: format!("Unknown escape sequence '\\{}'", chr),
+            }),
         }
-        return chr;
     }
 }
 
-impl Tokenizer for Lexer {
-    fn next_token(self: &mut Self) -> Result<Token, Error> {
+impl<'a> Lexer<'a> {
+    fn lex_token(self: &mut Lexer<'a>) -> Result<Token, Error> {
         loop {
             let start_location = self.location.clone();
             return match self.peek_char() {
                 '\0' => Ok(Token {
                     kind: TokenKind::EndOfFile,
-                    location: start_location.clone(),
-                    length: self.location.position - start_location.position,
+                    location: self.get_location_since(&start_location),
                     data: TokenData::None,
                 }),
 
@@ -117,30 +240,105 @@ impl Tokenizer for Lexer {
                     continue;
                 }
 
-                '0'..='9' => {
-                    let base = if self.peek_char() == '0' {
+                '/' if self.peek_char_at(1) == '*' => {
+                    self.next_char();
+                    self.next_char();
+
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match self.next_char() {
+                            '\0' => {
+                                return Err(Error {
+                                    location: start_location,
+                                    message: "Unterminated block comment".to_string(),
+                                })
+                            }
+
+                            '/' if self.peek_char() == '*' => {
+                                self.next_char();
+                                depth += 1;
+                            }
+
+                            '*' if self.peek_char() == '/' => {
+                                self.next_char();
+                                depth -= 1;
+                            }
+
+                            _ => {}
+                        }
+                    }
+
+                    continue;
+                }
+
+                '#' if self.peek_char_at(1) == '(' => {
+                    self.next_char();
+                    self.next_char();
+
+                    let mut text = String::new();
+                    loop {
+                        match self.next_char() {
+                            '\0' => {
+                                return Err(Error {
+                                    location: start_location,
+                                    message: "Unterminated stack effect comment".to_string(),
+                                })
+                            }
+                            ')' => break,
+                            chr => text.push(chr),
+                        }
+                    }
+
+                    Ok(Token {
+                        kind: TokenKind::StackEffect,
+                        location: self.get_location_since(&start_location),
+                        data: TokenData::String(text),
+                    })
+                }
+
+                // keywords like 2dup start with a digit, and ones like .s with a dot
+                '0'..='9' | '.' if LEXER_KEYWORDS.contains_key(&self.peek_name() as &str) => {
+                    let name = self.peek_name();
+                    for _ in name.chars() {
                         self.next_char();
-                        match self.peek_char() {
-                            'b' => 2,
-                            'o' => 8,
-                            'd' => 10,
-                            'x' => 16,
-                            _ => 10,
+                    }
+                    Ok(Token {
+                        kind: LEXER_KEYWORDS[&name as &str].clone(),
+                        location: self.get_location_since(&start_location),
+                        data: TokenData::None,
+                    })
+                }
+
+                '0'..='9' => {
+                    let prefix_base = if self.peek_char() == '0' {
+                        match self.peek_char_at(1) {
+                            'b' => Some(2),
+                            'o' => Some(8),
+                            'd' => Some(10),
+                            'x' => Some(16),
+                            _ => None,
                         }
                     } else {
-                        10
+                        None
                     };
+                    if prefix_base.is_some() {
+                        self.next_char();
+                        self.next_char();
+                    }
+                    let base = prefix_base.unwrap_or(10);
 
-                    let mut int_value = 0;
+                    let mut int_value: i64 = 0;
+                    let mut digit_count = 0;
+                    let mut after_digit = false;
 
                     loop {
                         let chr = self.peek_char();
                         match chr {
                             '0'..='9' | 'A'..='Z' | 'a'..='z' => {
                                 let value = match chr {
-                                    '0'..='9' => chr as isize - '0' as isize,
-                                    'A'..='Z' => chr as isize - 'A' as isize + 10,
-                                    'a'..='z' => chr as isize - 'a' as isize + 10,
+                                    '0'..='9' => chr as i64 - '0' as i64,
+                                    'A'..='Z' => chr as i64 - 'A' as i64 + 10,
+                                    'a'..='z' => chr as i64 - 'a' as i64 + 10,
                                     _ => unreachable!(),
                                 };
 
@@ -154,78 +352,185 @@ impl Tokenizer for Lexer {
                                     });
                                 }
 
-                                int_value *= base;
-                                int_value += value;
+                                int_value = int_value
+                                    .checked_mul(base)
+                                    .and_then(|int_value| int_value.checked_add(value))
+                                    .ok_or_else(|| Error {
+                                        location: start_location.clone(),
+                                        message: "Integer literal is too large".to_string(),
+                                    })?;
+                                digit_count += 1;
+                                after_digit = true;
 
                                 self.next_char();
                             }
 
                             '_' => {
+                                self.check_digit_separator(after_digit)?;
+                                after_digit = false;
                                 self.next_char();
-                                continue;
                             }
 
                             _ => break,
                         }
                     }
 
+                    if digit_count == 0 {
+                        return Err(Error {
+                            location: start_location,
+                            message: "Missing digits after base prefix".to_string(),
+                        });
+                    }
+
+                    if prefix_base.is_none()
+                        && self.peek_char() == '.'
+                        && self.peek_char_at(1).is_ascii_digit()
+                    {
+                        self.next_char();
+                        while let chr @ ('0'..='9' | '_') = self.peek_char() {
+                            if chr == '_' {
+                                self.check_digit_separator(true)?;
+                            }
+                            self.next_char();
+                        }
+
+                        if self.peek_char() == '.' {
+                            return Err(Error {
+                                location: self.location.clone(),
+                                message: "Float literal can only contain one '.'".to_string(),
+                            });
+                        }
+
+                        let text: String = self.source
+                            [start_location.position..self.location.position]
+                            .chars()
+                            .filter(|&chr| chr != '_')
+                            .collect();
+                        let value = text.parse().map_err(|_| Error {
+                            location: start_location.clone(),
+                            message: format!("Invalid float literal '{}'", text),
+                        })?;
+
+                        return Ok(Token {
+                            kind: TokenKind::Float,
+                            location: self.get_location_since(&start_location),
+                            data: TokenData::Float(value),
+                        });
+                    }
+
                     Ok(Token {
                         kind: TokenKind::Integer,
-                        location: start_location.clone(),
-                        length: self.location.position - start_location.position,
+                        location: self.get_location_since(&start_location),
                         data: TokenData::Integer(int_value),
                     })
                 }
 
-                'A'..='Z' | 'a'..='z' | '_' => {
-                    let mut name = String::new();
+                '"' => {
+                    self.next_char();
+
+                    let mut string = String::new();
                     loop {
                         match self.peek_char() {
-                            'A'..='Z' | 'a'..='z' | '0'..='9' | '_' => name.push(self.next_char()),
-                            _ => break,
+                            '"' => break,
+
+                            '\0' => {
+                                return Err(Error {
+                                    location: start_location,
+                                    message: "Unterminated string literal".to_string(),
+                                })
+                            }
+
+                            _ => string.push(self.next_escaped_char()?),
                         }
                     }
+                    self.next_char();
+
+                    Ok(Token {
+                        kind: TokenKind::String,
+                        location: self.get_location_since(&start_location),
+                        data: TokenData::String(string),
+                    })
+                }
+
+                '\'' => {
+                    self.next_char();
+
+                    if let '\'' | '\0' = self.peek_char() {
+                        return Err(Error {
+                            location: start_location,
+                            message: "Empty character literal".to_string(),
+                        });
+                    }
+
+                    let chr = self.next_escaped_char()?;
+
+                    match self.peek_char() {
+                        '\'' => {
+                            self.next_char();
+                        }
+
+                        '\0' => {
+                            return Err(Error {
+                                location: start_location,
+                                message: "Unterminated character literal".to_string(),
+                            })
+                        }
+
+                        _ => {
+                            return Err(Error {
+                                location: start_location,
+                                message: "Character literal may only contain one character"
+                                    .to_string(),
+                            })
+                        }
+                    }
+
+                    Ok(Token {
+                        kind: TokenKind::Integer,
+                        location: self.get_location_since(&start_location),
+                        data: TokenData::Integer(chr as i64),
+                    })
+                }
+
+                'A'..='Z' | 'a'..='z' | '_' => {
+                    let mut name = String::new();
+                    while let 'A'..='Z' | 'a'..='z' | '0'..='9' | '_' = self.peek_char() {
+                        name.push(self.next_char());
+                    }
                     if LEXER_KEYWORDS.contains_key(&name as &str) {
                         Ok(Token {
                             kind: LEXER_KEYWORDS[&name as &str].clone(),
-                            location: start_location.clone(),
-                            length: self.location.position - start_location.position,
+                            location: self.get_location_since(&start_location),
                             data: TokenData::None,
                         })
                     } else {
                         Ok(Token {
                             kind: TokenKind::Name,
-                            location: start_location.clone(),
-                            length: self.location.position - start_location.position,
+                            location: self.get_location_since(&start_location),
                             data: TokenData::String(name),
                         })
                     }
                 }
 
                 _ => {
-                    let chr = self.next_char();
-
-                    if LEXER_DOUBLE_CHARS.contains_key(&chr) {
-                        if LEXER_DOUBLE_CHARS[&chr].contains_key(&self.peek_char()) {
-                            let chr2 = self.next_char();
+                    for (operator, kind) in LEXER_OPERATORS.iter() {
+                        if operator
+                            .chars()
+                            .enumerate()
+                            .all(|(offset, chr)| self.peek_char_at(offset) == chr)
+                        {
+                            for _ in operator.chars() {
+                                self.next_char();
+                            }
                             return Ok(Token {
-                                kind: LEXER_DOUBLE_CHARS[&chr][&chr2].clone(),
-                                location: start_location.clone(),
-                                length: self.location.position - start_location.position,
+                                kind: kind.clone(),
+                                location: self.get_location_since(&start_location),
                                 data: TokenData::None,
                             });
                         }
                     }
 
-                    if LEXER_SINGLE_CHARS.contains_key(&chr) {
-                        return Ok(Token {
-                            kind: LEXER_SINGLE_CHARS[&chr].clone(),
-                            location: start_location.clone(),
-                            length: self.location.position - start_location.position,
-                            data: TokenData::None,
-                        });
-                    }
-
+                    let chr = self.next_char();
                     Err(Error {
                         location: start_location,
                         message: format!("Unknown character '{}'", chr),
@@ -234,13 +539,36 @@ impl Tokenizer for Lexer {
             };
         }
     }
+}
+
+impl Tokenizer for Lexer<'_> {
+    fn next_token(self: &mut Self) -> Result<Token, Error> {
+        if let Some((token, location)) = self.peeked.get_mut().take() {
+            self.location = location;
+            return token;
+        }
+        self.lex_token()
+    }
 
     fn peek_token(self: &Self) -> Result<Token, Error> {
+        if let Some((token, _)) = &*self.peeked.borrow() {
+            return token.clone();
+        }
         let mut lexer = self.clone();
-        return lexer.next_token();
+        let token = lexer.lex_token();
+        *self.peeked.borrow_mut() = Some((token.clone(), lexer.location));
+        token
     }
 
     fn peek_kind(self: &Self) -> Result<TokenKind, Error> {
         Ok(self.peek_token()?.kind)
     }
+
+    fn peek_nth(self: &Self, n: usize) -> Result<Token, Error> {
+        let mut lexer = self.clone();
+        for _ in 0..n {
+            lexer.next_token()?;
+        }
+        lexer.next_token()
+    }
 }
@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+#![allow(clippy::needless_arbitrary_self_type)]
+
+use compile::{compile_ir, GlobalDeclaration, ProcDeclaration};
+use ir_execution::run_ir;
+use ir_type_checking::type_check_ir;
+use lexer::Lexer;
+
+pub use common::Error;
+pub use ir::{IRKind, IR};
+pub use types::Type;
+
+pub mod backend_wasm;
+pub mod backend_x86;
+pub mod bytecode;
+pub mod common;
+pub mod compile;
+pub mod ir;
+pub mod ir_execution;
+pub mod ir_text;
+pub mod ir_type_checking;
+pub mod ir_verify;
+pub mod lexer;
+pub mod lint;
+pub mod optimize;
+pub mod repl;
+pub mod token;
+pub mod tokenizer;
+pub mod types;
+
+/// Everything `compile_str` produces, `global_declarations` is what `run_named` looks procedures up in
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub procedures: Vec<Vec<IR>>,
+    pub signatures: Vec<Type>,
+    pub proc_declarations: Vec<ProcDeclaration>,
+    pub global_declarations: Vec<GlobalDeclaration>,
+}
+
+/// Compiles and type checks `source`, `filepath` is only used for error locations
+pub fn compile_str(filepath: &str, source: &str) -> Result<Compiled, Error> {
+    let mut lexer = Lexer::new(filepath.to_string(), source);
+    let mut procedures = Vec::new();
+    let mut signatures = Vec::new();
+    let mut proc_declarations = Vec::new();
+    let mut shadowed_declarations = Vec::new();
+    let mut global_declarations = Vec::new();
+    compile_ir(
+        &mut lexer,
+        &mut procedures,
+        &mut signatures,
+        &mut proc_declarations,
+        &mut shadowed_declarations,
+        &mut global_declarations,
+    )?;
+    type_check_ir(&procedures, &signatures)?;
+    Ok(Compiled {
+        procedures,
+        signatures,
+        proc_declarations,
+        global_declarations,
+    })
+}
+
+/// Runs the program, returning its exit code
+pub fn run(procedures: &[Vec<IR>]) -> Result<i64, Error> {
+    run_ir(procedures)
+}
@@ -0,0 +1,56 @@
+use crate::{
+    common::{Diagnostic, Severity},
+    compile::{ProcDeclaration, ShadowedDeclaration},
+    ir::{IRKind, IR},
+};
+
+/// Finds the named procedures that are never pushed by any procedure other than themselves,
+/// each warning points at the procedure's name
+pub fn find_unused_procedures(
+    procedures: &[Vec<IR>],
+    proc_declarations: &[ProcDeclaration],
+) -> Vec<Diagnostic> {
+    let mut used = vec![false; procedures.len()];
+    for (id, procedure) in procedures.iter().enumerate() {
+        for ir in procedure {
+            if let IRKind::PushProc { id: pushed_id } = ir.kind {
+                if pushed_id != id {
+                    used[pushed_id] = true;
+                }
+            }
+        }
+    }
+
+    proc_declarations
+        .iter()
+        .filter(|declaration| !used[declaration.id])
+        .map(|declaration| Diagnostic {
+            severity: Severity::Warning,
+            location: declaration.location.clone(),
+            message: format!("Procedure '{}' is never used", declaration.name),
+        })
+        .collect()
+}
+
+/// Warns about each declaration that shadows another, with a note pointing at the declaration it shadows
+pub fn find_shadowed_declarations(
+    shadowed_declarations: &[ShadowedDeclaration],
+) -> Vec<Diagnostic> {
+    shadowed_declarations
+        .iter()
+        .flat_map(|shadowed| {
+            [
+                Diagnostic {
+                    severity: Severity::Warning,
+                    location: shadowed.location.clone(),
+                    message: format!("'{}' shadows an earlier declaration", shadowed.name),
+                },
+                Diagnostic {
+                    severity: Severity::Note,
+                    location: shadowed.shadowed_location.clone(),
+                    message: format!("'{}' was declared here", shadowed.name),
+                },
+            ]
+        })
+        .collect()
+}
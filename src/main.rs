@@ -1,48 +1,323 @@
-#![allow(dead_code)]
+use std::{
+    env::args,
+    io::{stderr, stdin, stdout, BufRead, IsTerminal, Write},
+    process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use std::{env::args, process::exit};
+use sbl::{
+    backend_wasm::emit_wat,
+    backend_x86::emit_nasm,
+    bytecode::{deserialize, serialize, serialize_with_debug},
+    common::{render_diagnostic_with_color, render_error_with_color, Diagnostic, Error, Severity},
+    compile::compile_ir,
+    ir_execution::{run_ir_with, OverflowMode, RunOptions},
+    ir_text::{dump, parse},
+    ir_type_checking::type_check_ir,
+    ir_verify::verify,
+    lexer::Lexer,
+    lint::{find_shadowed_declarations, find_unused_procedures},
+    optimize::{self, pool_constants},
+    repl::Repl,
+    token::{TokenData, TokenKind},
+    tokenizer::Tokenizer,
+};
 
-use compile::compile_ir;
+fn print_usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--run] [--run-bc] [--run-ir] [--dump-tokens] [--tab-width <n>] [--fuel <n>] [--overflow wrapping|checked] [--no-typecheck] [--warn-unused] [--warn-shadow] [-Werror] [--no-color] [-O0|-O1|-O2] [--emit asm|wat|bc|bc-debug] <file>",
+        program
+    );
+    eprintln!(
+        "       {} repl [--fuel <n>] [--overflow wrapping|checked] [--no-color]",
+        program
+    );
+    exit(1)
+}
+
+/// Exits with the code the program stopped with, flushing output that `emit` may have left without a newline
+fn exit_with_code(code: i64) -> ! {
+    stdout().flush().unwrap();
+    exit(code as i32)
+}
+
+fn print_error(error: Error) -> ! {
+    eprintln!("{}", error);
+    exit(1)
+}
+
+/// Whether diagnostics are printed with colors, set once the arguments have been read
+static USE_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Renders `error` against the file it points into, which may be an included file rather than `source`
+fn render_source_error(source: &str, error: &Error) -> String {
+    let color = USE_COLOR.load(Ordering::Relaxed);
+    match std::fs::read_to_string(&error.location.filepath) {
+        Ok(error_source) => render_error_with_color(&error_source, error, color),
+        Err(_) => render_error_with_color(source, error, color),
+    }
+}
+
+fn render_source_diagnostic(source: &str, diagnostic: &Diagnostic) -> String {
+    let color = USE_COLOR.load(Ordering::Relaxed);
+    match std::fs::read_to_string(&diagnostic.location.filepath) {
+        Ok(diagnostic_source) => {
+            render_diagnostic_with_color(&diagnostic_source, diagnostic, color)
+        }
+        Err(_) => render_diagnostic_with_color(source, diagnostic, color),
+    }
+}
 
-use crate::lexer::Lexer;
+fn print_source_error(source: &str, error: Error) -> ! {
+    eprintln!("{}", render_source_error(source, &error));
+    exit(1)
+}
 
-mod common;
-mod compile;
-mod ir;
-mod lexer;
-mod token;
-mod tokenizer;
+/// Reads lines from stdin until it ends, printing the stack after each one
+fn run_repl(run_options: &mut RunOptions) {
+    let mut repl = Repl::new();
+    let mut lines = stdin().lock().lines();
+    loop {
+        print!("> ");
+        stdout().flush().unwrap();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            return;
+        };
+        match repl.eval_line(&line, run_options) {
+            Ok(Some(code)) => exit_with_code(code),
+            Ok(None) => println!(
+                "[{}]",
+                repl.get_stack()
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Err(error) => eprintln!("{}", render_source_error(&line, &error)),
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <file>", args[0]);
-        exit(1)
+    let repl = args.get(1).is_some_and(|arg| arg == "repl");
+
+    let mut run = false;
+    let mut run_bytecode = false;
+    let mut run_ir_text = false;
+    let mut dump_tokens = false;
+    let mut type_check = true;
+    let mut tab_width = 1;
+    let mut warn_unused = false;
+    let mut warn_shadow = false;
+    let mut warnings_are_errors = false;
+    let mut color = true;
+    let mut optimization_level = 0;
+    let mut fuel = None;
+    let mut overflow = OverflowMode::Wrapping;
+    let mut emit = None;
+    let mut filepath = None;
+    let mut arg_iter = args[if repl { 2 } else { 1 }..].iter();
+    while let Some(arg) = arg_iter.next() {
+        match arg as &str {
+            "--run" => run = true,
+            "--run-bc" => run_bytecode = true,
+            "--run-ir" => run_ir_text = true,
+            "--dump-tokens" => dump_tokens = true,
+            "--no-typecheck" => type_check = false,
+            "--warn-unused" => warn_unused = true,
+            "--warn-shadow" => warn_shadow = true,
+            "-Werror" => warnings_are_errors = true,
+            "--no-color" => color = false,
+            "-O0" => optimization_level = 0,
+            "-O1" => optimization_level = 1,
+            "-O2" => optimization_level = 2,
+            "--tab-width" => {
+                tab_width = arg_iter
+                    .next()
+                    .and_then(|arg| arg.parse().ok())
+                    .unwrap_or_else(|| print_usage(&args[0]))
+            }
+            "--fuel" => {
+                fuel = Some(
+                    arg_iter
+                        .next()
+                        .and_then(|arg| arg.parse().ok())
+                        .unwrap_or_else(|| print_usage(&args[0])),
+                )
+            }
+            "--overflow" => match arg_iter.next().map(|arg| arg as &str) {
+                Some("wrapping") => overflow = OverflowMode::Wrapping,
+                Some("checked") => overflow = OverflowMode::Checked,
+                _ => print_usage(&args[0]),
+            },
+            "--emit" => match arg_iter.next().map(|arg| arg as &str) {
+                Some("asm") => emit = Some("asm"),
+                Some("wat") => emit = Some("wat"),
+                Some("bc") => emit = Some("bc"),
+                Some("bc-debug") => emit = Some("bc-debug"),
+                _ => print_usage(&args[0]),
+            },
+            _ if filepath.is_none() && !arg.starts_with("--") => filepath = Some(arg.clone()),
+            _ => print_usage(&args[0]),
+        }
     }
 
-    let filepath = &args[1];
+    // https://no-color.org, any value other than an empty one turns colors off
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    USE_COLOR.store(
+        color && !no_color_env && stderr().is_terminal(),
+        Ordering::Relaxed,
+    );
+
+    let mut out = stdout();
+    let mut run_options = RunOptions::new(&mut out);
+    run_options.fuel = fuel;
+    run_options.overflow = overflow;
 
-    let source = std::fs::read_to_string(filepath).unwrap_or_else(|_| {
+    if repl {
+        run_repl(&mut run_options);
+        return;
+    }
+    let filepath = filepath.unwrap_or_else(|| print_usage(&args[0]));
+
+    if run_bytecode {
+        let bytes = std::fs::read(&filepath).unwrap_or_else(|_| {
+            eprintln!("Unable to open file '{}'", filepath);
+            exit(1)
+        });
+        let (procedures, constants) =
+            deserialize(&bytes).unwrap_or_else(|error| print_error(error));
+        verify(&procedures).unwrap_or_else(|error| print_error(error));
+        run_options.constants = &constants;
+        let code =
+            run_ir_with(&procedures, &mut run_options).unwrap_or_else(|error| print_error(error));
+        exit_with_code(code);
+    }
+
+    let source = std::fs::read_to_string(&filepath).unwrap_or_else(|_| {
         eprintln!("Unable to open file '{}'", filepath);
         exit(1)
     });
 
-    let mut lexer = Lexer::new(filepath.clone(), &source as &str);
+    if run_ir_text {
+        let procedures = parse(&source).unwrap_or_else(|error| print_source_error(&source, error));
+        verify(&procedures).unwrap_or_else(|error| print_source_error(&source, error));
+        let code = run_ir_with(&procedures, &mut run_options)
+            .unwrap_or_else(|error| print_source_error(&source, error));
+        exit_with_code(code);
+    }
+
+    let mut lexer = Lexer::with_tab_width(filepath.clone(), &source as &str, tab_width);
+
+    if dump_tokens {
+        loop {
+            let token = lexer
+                .next_token()
+                .unwrap_or_else(|error| print_source_error(&source, error));
+            let data = match &token.data {
+                TokenData::None => String::new(),
+                TokenData::Integer(value) => format!(" {}", value),
+                TokenData::Float(value) => format!(" {:?}", value),
+                TokenData::String(value) => format!(" {:?}", value),
+            };
+            println!(
+                "{}:{}:{}: {}{}",
+                token.location.filepath,
+                token.location.line,
+                token.location.column,
+                token.kind,
+                data
+            );
+            if token.kind == TokenKind::EndOfFile {
+                return;
+            }
+        }
+    }
     let mut procedures = Vec::new();
+    let mut signatures = Vec::new();
+    let mut proc_declarations = Vec::new();
+    let mut shadowed_declarations = Vec::new();
+    let mut global_declarations = Vec::new();
 
-    compile_ir(&mut lexer, &mut procedures).unwrap_or_else(|error| {
-        eprintln!(
-            "{}:{}:{}: {}",
-            error.location.filepath, error.location.line, error.location.column, error.message
-        );
-        exit(1)
-    });
+    compile_ir(
+        &mut lexer,
+        &mut procedures,
+        &mut signatures,
+        &mut proc_declarations,
+        &mut shadowed_declarations,
+        &mut global_declarations,
+    )
+    .unwrap_or_else(|error| print_source_error(&source, error));
+    let mut diagnostics = Vec::new();
+    if warn_shadow {
+        diagnostics.extend(find_shadowed_declarations(&shadowed_declarations));
+    }
+    if warn_unused {
+        diagnostics.extend(find_unused_procedures(&procedures, &proc_declarations));
+    }
+    if warnings_are_errors {
+        for diagnostic in &mut diagnostics {
+            if diagnostic.severity == Severity::Warning {
+                diagnostic.severity = Severity::Error;
+            }
+        }
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}", render_source_diagnostic(&source, diagnostic));
+    }
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        exit(1);
+    }
+    if type_check {
+        type_check_ir(&procedures, &signatures)
+            .unwrap_or_else(|error| print_source_error(&source, error));
+    }
+    optimize::run(&mut procedures, optimization_level);
 
-    for (procedure_index, procedure) in procedures.iter().enumerate() {
-        println!("Procedure: {}", procedure_index);
-        for (ir_index, ir) in procedure.iter().enumerate() {
-            println!("    {} = {:?}", ir_index, ir.kind);
+    match emit {
+        Some("asm") => {
+            print!(
+                "{}",
+                emit_nasm(&procedures).unwrap_or_else(|error| print_source_error(&source, error))
+            );
+            return;
         }
-        println!();
+
+        Some("wat") => {
+            print!(
+                "{}",
+                emit_wat(&procedures).unwrap_or_else(|error| print_source_error(&source, error))
+            );
+            return;
+        }
+
+        Some("bc") | Some("bc-debug") => {
+            let constants = pool_constants(&mut procedures);
+            let bytes = if emit == Some("bc") {
+                serialize(&procedures, &constants)
+            } else {
+                serialize_with_debug(&procedures, &constants)
+            };
+            stdout().write_all(&bytes).unwrap_or_else(|_| {
+                eprintln!("Unable to write bytecode");
+                exit(1)
+            });
+            return;
+        }
+
+        _ => {}
     }
+
+    if run {
+        let code = run_ir_with(&procedures, &mut run_options)
+            .unwrap_or_else(|error| print_source_error(&source, error));
+        exit_with_code(code);
+    }
+
+    print!("{}", dump(&procedures));
 }
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use crate::{
+    ir::{get_local_count, IRKind, IR},
+    ir_execution::Value,
+};
+
+fn jump_target(position: usize, relative_position: isize) -> usize {
+    (position as isize + relative_position) as usize
+}
+
+fn successors(procedure: &[IR], position: usize) -> Vec<usize> {
+    match &procedure[position].kind {
+        IRKind::Exit | IRKind::ExitWithCode | IRKind::Return | IRKind::TailCall => vec![],
+        IRKind::Jump { relative_position } => vec![jump_target(position, *relative_position)],
+        IRKind::JumpFalse { relative_position } | IRKind::JumpTrue { relative_position } => {
+            vec![position + 1, jump_target(position, *relative_position)]
+        }
+        _ => vec![position + 1],
+    }
+}
+
+/// Gets whether each position of `procedure`, including the one just past its end, is the target of a jump
+fn get_jumped_to(procedure: &[IR]) -> Vec<bool> {
+    let mut jumped_to = vec![false; procedure.len() + 1];
+    for (position, ir) in procedure.iter().enumerate() {
+        if let IRKind::Jump { relative_position }
+        | IRKind::JumpFalse { relative_position }
+        | IRKind::JumpTrue { relative_position } = ir.kind
+        {
+            jumped_to[jump_target(position, relative_position)] = true;
+        }
+    }
+    jumped_to
+}
+
+/// Removes the instructions from `procedure` where `keep` is false, fixing up the relative jumps.
+/// A kept jump to a removed instruction will land on the next kept instruction instead.
+fn remove_instructions(procedure: &mut Vec<IR>, keep: &[bool]) {
+    let mut new_positions = Vec::with_capacity(procedure.len() + 1);
+    let mut new_position = 0;
+    for &kept in keep {
+        new_positions.push(new_position);
+        if kept {
+            new_position += 1;
+        }
+    }
+    new_positions.push(new_position);
+
+    let old_procedure = std::mem::take(procedure);
+    for (position, mut ir) in old_procedure.into_iter().enumerate() {
+        if !keep[position] {
+            continue;
+        }
+        if let IRKind::Jump { relative_position }
+        | IRKind::JumpFalse { relative_position }
+        | IRKind::JumpTrue { relative_position } = &mut ir.kind
+        {
+            let target = jump_target(position, *relative_position);
+            *relative_position = new_positions[target] as isize - new_positions[position] as isize;
+        }
+        procedure.push(ir);
+    }
+}
+
+/// Points every jump that lands on an unconditional `Jump` straight at where that jump goes,
+/// following chains of jumps. Jumps that are no longer reached are left for `eliminate_dead_code` to remove.
+pub fn thread_jumps(procedures: &mut [Vec<IR>]) {
+    for procedure in procedures.iter_mut() {
+        for position in 0..procedure.len() {
+            let relative_position = match procedure[position].kind {
+                IRKind::Jump { relative_position }
+                | IRKind::JumpFalse { relative_position }
+                | IRKind::JumpTrue { relative_position } => relative_position,
+                _ => continue,
+            };
+
+            let mut target = jump_target(position, relative_position);
+            // a chain that loops back on itself never leaves, so it is left as it is
+            let mut seen = vec![position];
+            while let Some(IRKind::Jump { relative_position }) =
+                procedure.get(target).map(|ir| &ir.kind)
+            {
+                if seen.contains(&target) {
+                    break;
+                }
+                seen.push(target);
+                target = jump_target(target, *relative_position);
+            }
+
+            if let IRKind::Jump { relative_position }
+            | IRKind::JumpFalse { relative_position }
+            | IRKind::JumpTrue { relative_position } = &mut procedure[position].kind
+            {
+                *relative_position = target as isize - position as isize;
+            }
+        }
+    }
+}
+
+/// Replaces every `Call` that is directly followed by a `Return` with a `TailCall`,
+/// the `Return` is left in place in case something jumps to it
+pub fn optimize_tail_calls(procedures: &mut [Vec<IR>]) {
+    for procedure in procedures.iter_mut() {
+        for position in 1..procedure.len() {
+            if procedure[position - 1].kind == IRKind::Call
+                && procedure[position].kind == IRKind::Return
+            {
+                procedure[position - 1].kind = IRKind::TailCall;
+            }
+        }
+    }
+}
+
+/// Resolves every conditional jump whose condition is pushed by a `PushBool` directly before it,
+/// a condition that never jumps removes the jump and one that always jumps makes it unconditional.
+/// Dead code elimination is run afterwards to remove the branches that can no longer be reached.
+pub fn fold_branches(procedures: &mut Vec<Vec<IR>>) {
+    for procedure in procedures.iter_mut() {
+        let jumped_to = get_jumped_to(procedure);
+
+        let mut keep = vec![true; procedure.len()];
+        for position in 1..procedure.len() {
+            // something else could reach the jump with a different condition on the stack
+            if jumped_to[position] {
+                continue;
+            }
+            let (value, relative_position, jumps_when) =
+                match (&procedure[position - 1].kind, &procedure[position].kind) {
+                    (IRKind::PushBool { value }, IRKind::JumpFalse { relative_position }) => {
+                        (*value, *relative_position, false)
+                    }
+                    (IRKind::PushBool { value }, IRKind::JumpTrue { relative_position }) => {
+                        (*value, *relative_position, true)
+                    }
+                    _ => continue,
+                };
+            keep[position - 1] = false;
+            if value == jumps_when {
+                procedure[position].kind = IRKind::Jump { relative_position };
+            } else {
+                keep[position] = false;
+            }
+        }
+        remove_instructions(procedure, &keep);
+    }
+
+    eliminate_dead_code(procedures);
+}
+
+/// Gets the result of an operation on two constant integers,
+/// `None` if it can not be done at compile time because it would fail or overflow when run
+fn fold_integers(kind: &IRKind, a: i64, b: i64) -> Option<i64> {
+    match kind {
+        IRKind::Add => a.checked_add(b),
+        IRKind::Sub => a.checked_sub(b),
+        IRKind::Mul => a.checked_mul(b),
+        IRKind::Div => a.checked_div(b),
+        IRKind::Mod => a.checked_rem(b),
+        IRKind::BitAnd => Some(a & b),
+        IRKind::BitOr => Some(a | b),
+        IRKind::BitXor => Some(a ^ b),
+        IRKind::ShiftLeft if (0..i64::BITS as i64).contains(&b) => Some(a << b),
+        IRKind::ShiftRight if (0..i64::BITS as i64).contains(&b) => Some(a >> b),
+        IRKind::Max => Some(a.max(b)),
+        IRKind::Min => Some(a.min(b)),
+        _ => None,
+    }
+}
+
+fn compare_integers(kind: &IRKind, a: i64, b: i64) -> Option<bool> {
+    match kind {
+        IRKind::LessThan => Some(a < b),
+        IRKind::GreaterThan => Some(a > b),
+        IRKind::LessThanEqual => Some(a <= b),
+        IRKind::GreaterThanEqual => Some(a >= b),
+        IRKind::Equal => Some(a == b),
+        IRKind::NotEqual => Some(a != b),
+        _ => None,
+    }
+}
+
+fn fold_bools(kind: &IRKind, a: bool, b: bool) -> Option<bool> {
+    match kind {
+        IRKind::And => Some(a && b),
+        IRKind::Or => Some(a || b),
+        IRKind::Equal => Some(a == b),
+        IRKind::NotEqual => Some(a != b),
+        _ => None,
+    }
+}
+
+/// Replaces operations on constants pushed directly before them with the constant result,
+/// until there is nothing left to fold. Nothing is folded if something jumps between the instructions.
+pub fn fold_constants(procedures: &mut [Vec<IR>]) {
+    for procedure in procedures.iter_mut() {
+        loop {
+            let jumped_to = get_jumped_to(procedure);
+            let mut keep = vec![true; procedure.len()];
+            let mut changed = false;
+            for position in 1..procedure.len() {
+                if !keep[position - 1] || jumped_to[position] {
+                    continue;
+                }
+
+                let unary = match (&procedure[position - 1].kind, &procedure[position].kind) {
+                    (IRKind::PushInt { value }, IRKind::Neg) => {
+                        value.checked_neg().map(|value| IRKind::PushInt { value })
+                    }
+                    (IRKind::PushBool { value }, IRKind::Not) => {
+                        Some(IRKind::PushBool { value: !value })
+                    }
+                    _ => None,
+                };
+                if let Some(kind) = unary {
+                    keep[position - 1] = false;
+                    procedure[position].kind = kind;
+                    changed = true;
+                    continue;
+                }
+
+                if position < 2 || !keep[position - 2] || jumped_to[position - 1] {
+                    continue;
+                }
+                let binary = match (
+                    &procedure[position - 2].kind,
+                    &procedure[position - 1].kind,
+                    &procedure[position].kind,
+                ) {
+                    (IRKind::PushInt { value: a }, IRKind::PushInt { value: b }, kind) => {
+                        fold_integers(kind, *a, *b)
+                            .map(|value| IRKind::PushInt { value })
+                            .or_else(|| {
+                                compare_integers(kind, *a, *b)
+                                    .map(|value| IRKind::PushBool { value })
+                            })
+                    }
+                    (IRKind::PushBool { value: a }, IRKind::PushBool { value: b }, kind) => {
+                        fold_bools(kind, *a, *b).map(|value| IRKind::PushBool { value })
+                    }
+                    _ => None,
+                };
+                if let Some(kind) = binary {
+                    keep[position - 2] = false;
+                    keep[position - 1] = false;
+                    procedure[position].kind = kind;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            remove_instructions(procedure, &keep);
+        }
+    }
+}
+
+/// Gets the instructions of `procedure` before its `Return` if it is small enough to inline,
+/// procedures that return from anywhere else are never inlined
+fn get_inline_body(procedure: &[IR], threshold: usize) -> Option<&[IR]> {
+    let (last, body) = procedure.split_last()?;
+    if last.kind != IRKind::Return
+        || body.len() >= threshold
+        || body
+            .iter()
+            .any(|ir| matches!(ir.kind, IRKind::Return | IRKind::TailCall))
+    {
+        return None;
+    }
+    Some(body)
+}
+
+/// Replaces direct calls, a `PushProc` directly followed by a `Call`, with the body of the called procedure
+/// when it has fewer than `threshold` instructions before its `Return`. Only the bodies as they were before
+/// this pass are inlined, so recursive procedures are inlined one level deep at most.
+/// The locals of an inlined body are moved after the locals of the procedure it is inlined into.
+pub fn inline(procedures: &mut [Vec<IR>], threshold: usize) {
+    let originals = procedures.to_vec();
+    for (id, procedure) in procedures.iter_mut().enumerate() {
+        let jumped_to = get_jumped_to(procedure);
+        let local_offset = get_local_count(procedure);
+
+        let old_procedure = std::mem::take(procedure);
+        let mut new_positions = Vec::with_capacity(old_procedure.len() + 1);
+        // the jumps of the procedure itself, with the old position they jump to
+        let mut jumps = Vec::new();
+        let mut position = 0;
+        while position < old_procedure.len() {
+            new_positions.push(procedure.len());
+            let body = match (
+                &old_procedure[position].kind,
+                old_procedure.get(position + 1).map(|ir| &ir.kind),
+            ) {
+                (IRKind::PushProc { id: callee }, Some(IRKind::Call))
+                    if *callee != id && !jumped_to[position + 1] =>
+                {
+                    get_inline_body(&originals[*callee], threshold)
+                }
+                _ => None,
+            };
+
+            if let Some(body) = body {
+                new_positions.push(procedure.len());
+                // the jumps in the body are relative, and a jump to its `Return` now lands after the body
+                procedure.extend(body.iter().cloned().map(|mut ir| {
+                    if let IRKind::StoreLocal { slot } | IRKind::LoadLocal { slot } = &mut ir.kind {
+                        *slot += local_offset;
+                    }
+                    ir
+                }));
+                position += 2;
+                continue;
+            }
+
+            let ir = old_procedure[position].clone();
+            if let IRKind::Jump { relative_position }
+            | IRKind::JumpFalse { relative_position }
+            | IRKind::JumpTrue { relative_position } = ir.kind
+            {
+                jumps.push((procedure.len(), jump_target(position, relative_position)));
+            }
+            procedure.push(ir);
+            position += 1;
+        }
+        new_positions.push(procedure.len());
+
+        for (new_position, old_target) in jumps {
+            if let IRKind::Jump { relative_position }
+            | IRKind::JumpFalse { relative_position }
+            | IRKind::JumpTrue { relative_position } = &mut procedure[new_position].kind
+            {
+                *relative_position = new_positions[old_target] as isize - new_position as isize;
+            }
+        }
+    }
+}
+
+/// How many instructions a procedure can have before `-O2` stops inlining it
+pub const INLINE_THRESHOLD: usize = 8;
+
+/// Runs the passes for an optimization level, 0 runs none, 1 folds constants and branches and cleans up with the peephole pass,
+/// 2 also inlines small procedures, threads jumps, removes dead code and turns calls before a return into tail calls.
+/// This has to be run after type checking, the passes expect the types to be correct.
+pub fn run(procedures: &mut Vec<Vec<IR>>, level: u8) {
+    if level == 0 {
+        return;
+    }
+    if level >= 2 {
+        inline(procedures, INLINE_THRESHOLD);
+    }
+    fold_constants(procedures);
+    fold_branches(procedures);
+    if level >= 2 {
+        thread_jumps(procedures);
+        eliminate_dead_code(procedures);
+    }
+    peephole(procedures);
+    if level >= 2 {
+        optimize_tail_calls(procedures);
+    }
+}
+
+fn cancels_out(first: &IRKind, second: &IRKind) -> bool {
+    matches!(
+        (first, second),
+        (IRKind::Dup, IRKind::Drop)
+            | (IRKind::Swap, IRKind::Swap)
+            | (IRKind::Not, IRKind::Not)
+            | (IRKind::PushInt { .. }, IRKind::Drop)
+            | (IRKind::PushBool { .. }, IRKind::Drop)
+            | (IRKind::PushFloat { .. }, IRKind::Drop)
+    )
+}
+
+fn is_commutative(kind: &IRKind) -> bool {
+    matches!(
+        kind,
+        IRKind::Add
+            | IRKind::Mul
+            | IRKind::Equal
+            | IRKind::NotEqual
+            | IRKind::BitAnd
+            | IRKind::BitOr
+            | IRKind::BitXor
+            | IRKind::And
+            | IRKind::Or
+            | IRKind::Max
+            | IRKind::Min
+    )
+}
+
+/// Removes pairs of adjacent instructions that have no effect together, such as `dup drop` or `swap swap`,
+/// `swap`s directly before an operation where the order of the operands does not matter and jumps to the next instruction,
+/// until there are none left. Nothing is removed if something jumps between the two instructions.
+pub fn peephole(procedures: &mut [Vec<IR>]) {
+    for procedure in procedures.iter_mut() {
+        loop {
+            let jumped_to = get_jumped_to(procedure);
+
+            let mut keep = vec![true; procedure.len()];
+            let mut changed = false;
+            for (position, ir) in procedure.iter().enumerate() {
+                if ir.kind
+                    == (IRKind::Jump {
+                        relative_position: 1,
+                    })
+                {
+                    keep[position] = false;
+                    changed = true;
+                }
+            }
+            for position in 1..procedure.len() {
+                if !keep[position - 1] || !keep[position] || jumped_to[position] {
+                    continue;
+                }
+                if cancels_out(&procedure[position - 1].kind, &procedure[position].kind) {
+                    keep[position - 1] = false;
+                    keep[position] = false;
+                    changed = true;
+                } else if procedure[position - 1].kind == IRKind::Swap
+                    && is_commutative(&procedure[position].kind)
+                {
+                    keep[position - 1] = false;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            remove_instructions(procedure, &keep);
+        }
+    }
+}
+
+/// Moves the values of every `PushInt` and `PushProc` into a constant pool, replacing them with a `PushConst`,
+/// equal values share a single slot. The returned pool has to be passed in `RunOptions::constants`.
+/// This should be run last, other passes do not look inside the pool for the procedures that are used.
+pub fn pool_constants(procedures: &mut [Vec<IR>]) -> Vec<Value> {
+    let mut constants = Vec::new();
+    let mut integer_indices: HashMap<i64, usize> = HashMap::new();
+    let mut procedure_indices: HashMap<usize, usize> = HashMap::new();
+    for ir in procedures.iter_mut().flatten() {
+        let index = match ir.kind {
+            IRKind::PushInt { value } => *integer_indices.entry(value).or_insert_with(|| {
+                constants.push(Value::Integer(value));
+                constants.len() - 1
+            }),
+            IRKind::PushProc { id } => *procedure_indices.entry(id).or_insert_with(|| {
+                constants.push(Value::Procedure(id));
+                constants.len() - 1
+            }),
+            _ => continue,
+        };
+        ir.kind = IRKind::PushConst { index };
+    }
+    constants
+}
+
+pub fn eliminate_dead_code(procedures: &mut Vec<Vec<IR>>) {
+    for procedure in procedures.iter_mut() {
+        let mut reachable = vec![false; procedure.len()];
+        let mut positions = vec![0];
+        while let Some(position) = positions.pop() {
+            if position >= procedure.len() || reachable[position] {
+                continue;
+            }
+            reachable[position] = true;
+            positions.extend(successors(procedure, position));
+        }
+        remove_instructions(procedure, &reachable);
+    }
+
+    let mut used = vec![false; procedures.len()];
+    let mut ids = vec![0];
+    while let Some(id) = ids.pop() {
+        if used[id] {
+            continue;
+        }
+        used[id] = true;
+        for ir in &procedures[id] {
+            if let IRKind::PushProc { id } = ir.kind {
+                ids.push(id);
+            }
+        }
+    }
+
+    let mut new_ids = Vec::with_capacity(procedures.len());
+    let mut new_id = 0;
+    for &is_used in &used {
+        new_ids.push(new_id);
+        if is_used {
+            new_id += 1;
+        }
+    }
+
+    let old_procedures = std::mem::take(procedures);
+    for (id, mut procedure) in old_procedures.into_iter().enumerate() {
+        if !used[id] {
+            continue;
+        }
+        for ir in &mut procedure {
+            if let IRKind::PushProc { id } = &mut ir.kind {
+                *id = new_ids[*id];
+            }
+        }
+        procedures.push(procedure);
+    }
+}
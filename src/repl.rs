@@ -0,0 +1,93 @@
+use crate::{
+    common::Error,
+    compile::{compile_more, CompileState, GlobalDeclaration, ProcDeclaration},
+    ir::{IRKind, IR},
+    ir_execution::{Machine, RunOptions, StepResult, Value},
+    ir_type_checking::type_check_ir,
+    lexer::Lexer,
+    types::Type,
+};
+
+/// Everything a REPL session keeps between lines, each line is compiled onto the end of the global code
+#[derive(Debug, Clone)]
+pub struct Repl {
+    procedures: Vec<Vec<IR>>,
+    signatures: Vec<Type>,
+    proc_declarations: Vec<ProcDeclaration>,
+    state: CompileState,
+    machine: Machine,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            procedures: Vec::new(),
+            signatures: Vec::new(),
+            proc_declarations: Vec::new(),
+            state: CompileState::new(),
+            machine: Machine::new(&[], 0, Vec::new()),
+        }
+    }
+
+    /// Compiles, type checks and runs `line` after all the lines before it, returning the exit code if it ran `exit`.
+    /// If anything fails the session is left as it was before the line, apart from any output already written
+    pub fn eval_line(
+        self: &mut Repl,
+        line: &str,
+        options: &mut RunOptions,
+    ) -> Result<Option<i64>, Error> {
+        let saved = self.clone();
+        let result = self.eval_line_unchecked(line, options);
+        if result.is_err() {
+            *self = saved;
+        }
+        result
+    }
+
+    fn eval_line_unchecked(
+        self: &mut Repl,
+        line: &str,
+        options: &mut RunOptions,
+    ) -> Result<Option<i64>, Error> {
+        // the new code goes where the `Exit` of the previous line was
+        let start = self.procedures.first().map_or(0, |global| global.len() - 1);
+        let mut lexer = Lexer::new("<repl>".to_string(), line);
+        compile_more(
+            &mut lexer,
+            &mut self.state,
+            &mut self.procedures,
+            &mut self.signatures,
+            &mut self.proc_declarations,
+            &mut Vec::new(),
+        )?;
+        // the earlier lines are checked again, but they have to give the stack the values it already has
+        type_check_ir(&self.procedures, &self.signatures)?;
+
+        self.machine.procedure = 0;
+        self.machine.ip = start;
+        match self.machine.run(&self.procedures, options)?.0 {
+            // the rest of the line was never run, so the session can not go on from here
+            StepResult::Halted(code)
+                if self.procedures[self.machine.procedure][self.machine.ip].kind
+                    == IRKind::ExitWithCode =>
+            {
+                Ok(Some(code))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_stack(self: &Repl) -> &[Value] {
+        &self.machine.stack
+    }
+
+    pub fn get_global_declarations(self: &Repl) -> Vec<GlobalDeclaration> {
+        self.state.get_global_declarations()
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
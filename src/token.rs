@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use crate::common::SourceLocation;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -5,16 +7,34 @@ pub enum TokenKind {
     EndOfFile,
 
     Integer,
+    Float,
+    String,
+    True,
+    False,
     Name,
+    /// A `#( ... -- ... )` comment, its data is the text between the parentheses
+    StackEffect,
 
     Print,
+    PrintStr,
+    PrintHex,
+    PrintBin,
+    Emit,
+    Assert,
+    Exit,
+    DumpStack,
+    TypeOf,
 
     If,
     Else,
+    Elif,
+    Include,
 
     While,
 
     Const,
+    Alias,
+    Let,
 
     Proc,
     Call,
@@ -22,8 +42,21 @@ pub enum TokenKind {
     Dup,
     Drop,
     Swap,
+    Over,
+    Rot,
+    TwoDup,
+    Nip,
+    Tuck,
+    DropN,
+    DupN,
+    Pick,
+    Roll,
 
     Memory,
+    Load,
+    Store,
+    At,
+    LeftArrow,
 
     OpenParenthesis,
     CloseParenthesis,
@@ -31,6 +64,11 @@ pub enum TokenKind {
     CloseBrace,
 
     Not,
+    Neg,
+    And,
+    Or,
+    Max,
+    Min,
 
     RightArrow,
 
@@ -38,6 +76,14 @@ pub enum TokenKind {
     Minus,
     Asterisk,
     Slash,
+    Percent,
+
+    Ampersand,
+    Pipe,
+    Caret,
+
+    ShiftLeft,
+    ShiftRight,
 
     LessThan,
     GreaterThan,
@@ -49,15 +95,111 @@ pub enum TokenKind {
     NotEqual,
 }
 
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::EndOfFile => write!(f, "end of file"),
+
+            TokenKind::Integer => write!(f, "integer"),
+            TokenKind::Float => write!(f, "float"),
+            TokenKind::String => write!(f, "string"),
+            TokenKind::True => write!(f, "true"),
+            TokenKind::False => write!(f, "false"),
+            TokenKind::Name => write!(f, "name"),
+            TokenKind::StackEffect => write!(f, "stack effect comment"),
+
+            TokenKind::Print => write!(f, "print"),
+            TokenKind::PrintStr => write!(f, "print_str"),
+            TokenKind::PrintHex => write!(f, "print_hex"),
+            TokenKind::PrintBin => write!(f, "print_bin"),
+            TokenKind::Emit => write!(f, "emit"),
+            TokenKind::Assert => write!(f, "assert"),
+            TokenKind::Exit => write!(f, "exit"),
+            TokenKind::DumpStack => write!(f, ".s"),
+            TokenKind::TypeOf => write!(f, "typeof"),
+
+            TokenKind::If => write!(f, "if"),
+            TokenKind::Else => write!(f, "else"),
+            TokenKind::Elif => write!(f, "elif"),
+            TokenKind::Include => write!(f, "include"),
+
+            TokenKind::While => write!(f, "while"),
+
+            TokenKind::Const => write!(f, "const"),
+            TokenKind::Alias => write!(f, "alias"),
+            TokenKind::Let => write!(f, "let"),
+
+            TokenKind::Proc => write!(f, "proc"),
+            TokenKind::Call => write!(f, "call"),
+
+            TokenKind::Dup => write!(f, "dup"),
+            TokenKind::Drop => write!(f, "drop"),
+            TokenKind::Swap => write!(f, "swap"),
+            TokenKind::Over => write!(f, "over"),
+            TokenKind::Rot => write!(f, "rot"),
+            TokenKind::TwoDup => write!(f, "2dup"),
+            TokenKind::Nip => write!(f, "nip"),
+            TokenKind::Tuck => write!(f, "tuck"),
+            TokenKind::DropN => write!(f, "dropn"),
+            TokenKind::DupN => write!(f, "dupn"),
+            TokenKind::Pick => write!(f, "pick"),
+            TokenKind::Roll => write!(f, "roll"),
+
+            TokenKind::Memory => write!(f, "memory"),
+            TokenKind::Load => write!(f, "load"),
+            TokenKind::Store => write!(f, "store"),
+            TokenKind::At => write!(f, "@"),
+            TokenKind::LeftArrow => write!(f, "<-"),
+
+            TokenKind::OpenParenthesis => write!(f, "("),
+            TokenKind::CloseParenthesis => write!(f, ")"),
+            TokenKind::OpenBrace => write!(f, "{{"),
+            TokenKind::CloseBrace => write!(f, "}}"),
+
+            TokenKind::Not => write!(f, "!"),
+            TokenKind::Neg => write!(f, "neg"),
+            TokenKind::And => write!(f, "and"),
+            TokenKind::Or => write!(f, "or"),
+            TokenKind::Max => write!(f, "max"),
+            TokenKind::Min => write!(f, "min"),
+
+            TokenKind::RightArrow => write!(f, "->"),
+
+            TokenKind::Plus => write!(f, "+"),
+            TokenKind::Minus => write!(f, "-"),
+            TokenKind::Asterisk => write!(f, "*"),
+            TokenKind::Slash => write!(f, "/"),
+            TokenKind::Percent => write!(f, "%"),
+
+            TokenKind::Ampersand => write!(f, "&"),
+            TokenKind::Pipe => write!(f, "|"),
+            TokenKind::Caret => write!(f, "^"),
+
+            TokenKind::ShiftLeft => write!(f, "<<"),
+            TokenKind::ShiftRight => write!(f, ">>"),
+
+            TokenKind::LessThan => write!(f, "<"),
+            TokenKind::GreaterThan => write!(f, ">"),
+            TokenKind::LessThanEqual => write!(f, "<="),
+            TokenKind::GreaterThanEqual => write!(f, ">="),
+
+            TokenKind::Equal => write!(f, "="),
+            TokenKind::EqualEqual => write!(f, "=="),
+            TokenKind::NotEqual => write!(f, "!="),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenData {
     None,
-    Integer(isize),
+    Integer(i64),
+    Float(f64),
     String(String),
 }
 
 impl TokenData {
-    pub fn get_integer(self: &TokenData) -> isize {
+    pub fn get_integer(self: &TokenData) -> i64 {
         if let TokenData::Integer(value) = self {
             *value
         } else {
@@ -65,7 +207,7 @@ impl TokenData {
         }
     }
 
-    pub fn get_integer_mut(self: &mut TokenData) -> &mut isize {
+    pub fn get_integer_mut(self: &mut TokenData) -> &mut i64 {
         if let TokenData::Integer(value) = self {
             value
         } else {
@@ -73,6 +215,14 @@ impl TokenData {
         }
     }
 
+    pub fn get_float(self: &TokenData) -> f64 {
+        if let TokenData::Float(value) = self {
+            *value
+        } else {
+            unreachable!()
+        }
+    }
+
     pub fn get_string(self: &TokenData) -> String {
         if let TokenData::String(value) = self {
             value.clone()
@@ -94,6 +244,5 @@ impl TokenData {
 pub struct Token {
     pub kind: TokenKind,
     pub location: SourceLocation,
-    pub length: usize,
     pub data: TokenData,
 }
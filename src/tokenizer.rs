@@ -1,3 +1,5 @@
+use std::{cell::RefCell, collections::VecDeque};
+
 use crate::{
     common::{Error, SourceLocation},
     token::{Token, TokenData, TokenKind},
@@ -7,6 +9,7 @@ pub trait Tokenizer {
     fn next_token(self: &mut Self) -> Result<Token, Error>;
     fn peek_token(self: &Self) -> Result<Token, Error>;
     fn peek_kind(self: &Self) -> Result<TokenKind, Error>;
+    fn peek_nth(self: &Self, n: usize) -> Result<Token, Error>;
 
     fn expect_token(self: &mut Self, kind: TokenKind) -> Result<Token, Error> {
         let actual_token = self.peek_token()?;
@@ -14,7 +17,7 @@ pub trait Tokenizer {
             Err(Error {
                 location: actual_token.location.clone(),
                 message: format!(
-                    "Unexpected token '{:?}', expected '{:?}'",
+                    "Unexpected token '{}', expected '{}'",
                     actual_token.kind, kind,
                 ),
             })
@@ -32,16 +35,26 @@ pub struct TokenArray {
 }
 
 impl TokenArray {
+    /// Creates a tokenizer that gives out `tokens` in order, `filepath` is used for the end of file token
+    pub fn new(filepath: String, tokens: Vec<Token>) -> TokenArray {
+        TokenArray {
+            filepath,
+            tokens,
+            position: 0,
+        }
+    }
+
     pub fn get_end_of_file_token(self: &TokenArray) -> Token {
         Token {
             kind: TokenKind::EndOfFile,
-            location: if self.tokens.len() > 0 {
+            location: if !self.tokens.is_empty() {
                 let last_token = self.tokens.last().unwrap();
                 SourceLocation {
                     filepath: self.filepath.clone(),
-                    position: last_token.location.position + last_token.length,
+                    position: last_token.location.position + last_token.location.length,
                     line: last_token.location.line,
-                    column: last_token.location.column + last_token.length,
+                    column: last_token.location.column + last_token.location.length,
+                    length: 0,
                 }
             } else {
                 SourceLocation {
@@ -49,9 +62,9 @@ impl TokenArray {
                     position: 0,
                     line: 1,
                     column: 1,
+                    length: 0,
                 }
             },
-            length: 0,
             data: TokenData::None,
         }
     }
@@ -83,4 +96,59 @@ impl Tokenizer for TokenArray {
             Ok(TokenKind::EndOfFile)
         }
     }
+
+    fn peek_nth(self: &Self, n: usize) -> Result<Token, Error> {
+        if self.position + n < self.tokens.len() {
+            Ok(self.tokens[self.position + n].clone())
+        } else {
+            Ok(self.get_end_of_file_token())
+        }
+    }
+}
+
+/// Passes every token of another tokenizer through `map` before giving it out, so tokens can be rewritten before they are compiled.
+/// Each token is mapped exactly once and in order, tokens that have been peeked are kept until they are taken.
+pub struct MapTokenizer<'a, F: FnMut(Token) -> Result<Token, Error>> {
+    inner: RefCell<Box<dyn Tokenizer + 'a>>,
+    map: RefCell<F>,
+    peeked: RefCell<VecDeque<Result<Token, Error>>>,
+}
+
+impl<'a, F: FnMut(Token) -> Result<Token, Error>> MapTokenizer<'a, F> {
+    pub fn new(inner: Box<dyn Tokenizer + 'a>, map: F) -> MapTokenizer<'a, F> {
+        MapTokenizer {
+            inner: RefCell::new(inner),
+            map: RefCell::new(map),
+            peeked: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Maps tokens from the inner tokenizer until there are more than `n` peeked
+    fn fill(self: &MapTokenizer<'a, F>, n: usize) {
+        let mut peeked = self.peeked.borrow_mut();
+        while peeked.len() <= n {
+            let token = self.inner.borrow_mut().next_token();
+            peeked.push_back(token.and_then(|token| (self.map.borrow_mut())(token)));
+        }
+    }
+}
+
+impl<F: FnMut(Token) -> Result<Token, Error>> Tokenizer for MapTokenizer<'_, F> {
+    fn next_token(self: &mut Self) -> Result<Token, Error> {
+        self.fill(0);
+        self.peeked.get_mut().pop_front().unwrap()
+    }
+
+    fn peek_token(self: &Self) -> Result<Token, Error> {
+        self.peek_nth(0)
+    }
+
+    fn peek_kind(self: &Self) -> Result<TokenKind, Error> {
+        Ok(self.peek_token()?.kind)
+    }
+
+    fn peek_nth(self: &Self, n: usize) -> Result<Token, Error> {
+        self.fill(n);
+        self.peeked.borrow()[n].clone()
+    }
 }
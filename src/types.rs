@@ -0,0 +1,44 @@
+use std::fmt::Display;
+
+/// The derived ordering follows the declaration order of the variants,
+/// procedures compare their parameters first and then their return types
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::enum_variant_names)]
+pub enum Type {
+    Type,
+    Integer,
+    Float,
+    Bool,
+    Procedure {
+        parameters: Vec<Type>,
+        return_types: Vec<Type>,
+    },
+}
+
+pub fn format_types(types: &[Type]) -> String {
+    types
+        .iter()
+        .map(|typ| typ.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Type => write!(f, "type"),
+            Type::Integer => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Procedure {
+                parameters,
+                return_types,
+            } => write!(
+                f,
+                "proc({}) -> ({})",
+                format_types(parameters),
+                format_types(return_types)
+            ),
+        }
+    }
+}
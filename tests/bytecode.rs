@@ -0,0 +1,113 @@
+mod common;
+
+use common::{compile, run, run_error, run_with_constants};
+use sbl::{
+    bytecode::{deserialize, serialize, serialize_with_debug},
+    ir_execution::Value,
+    ir_verify::verify,
+    optimize::{self, pool_constants},
+    IRKind, IR,
+};
+
+#[test]
+fn exit_round_trips_at_o2() {
+    // dead code elimination removes the `Exit` after `exit`
+    let mut procedures = compile("3 exit");
+    optimize::run(&mut procedures, 2);
+
+    let (procedures, constants) = deserialize(&serialize(&procedures, &[])).unwrap();
+    verify(&procedures).unwrap();
+    assert_eq!(run_with_constants(&procedures, &constants).1, 3);
+}
+
+#[test]
+fn infinite_loop_verifies_at_o2() {
+    let mut procedures = compile("proc spin () { while true { } } spin call");
+    optimize::run(&mut procedures, 2);
+
+    let (procedures, _) = deserialize(&serialize(&procedures, &[])).unwrap();
+    verify(&procedures).unwrap();
+}
+
+#[test]
+fn pooled_constants_are_stored_once() {
+    let source = "123456789 print 123456789 print 123456789 print";
+    let unpooled = compile(source);
+    let mut procedures = unpooled.clone();
+    let constants = pool_constants(&mut procedures);
+    assert_eq!(constants, vec![Value::Integer(123456789)]);
+
+    let bytes = serialize(&procedures, &constants);
+    assert!(bytes.len() < serialize(&unpooled, &[]).len());
+
+    let (procedures, constants) = deserialize(&bytes).unwrap();
+    verify(&procedures).unwrap();
+    assert_eq!(
+        run_with_constants(&procedures, &constants).0,
+        "123456789\n123456789\n123456789\n"
+    );
+}
+
+#[test]
+fn pooled_constants_keep_every_bit() {
+    // these only differ above the low 32 bits
+    let mut procedures = compile("1 print 4294967297 print");
+    let constants = pool_constants(&mut procedures);
+    assert_eq!(
+        constants,
+        vec![Value::Integer(1), Value::Integer(4294967297)]
+    );
+    assert_eq!(
+        run_with_constants(&procedures, &constants).0,
+        "1\n4294967297\n"
+    );
+}
+
+const PROGRAM: &str = "
+memory cell 8
+proc fib (int) -> (int) {
+    dup 2 < if { } else { dup 1 - fib call swap 2 - fib call + }
+}
+cell 10 fib call <-
+cell @ print
+1.5 2.5 + print
+\"text\\n\" print_str
+true false or if { int print } elif false { 1 print } else { 2 print }
+";
+
+fn without_locations(procedures: &[Vec<IR>]) -> Vec<Vec<IRKind>> {
+    procedures
+        .iter()
+        .map(|procedure| procedure.iter().map(|ir| ir.kind.clone()).collect())
+        .collect()
+}
+
+#[test]
+fn bytecode_round_trips() {
+    let procedures = compile(PROGRAM);
+    let (decoded, constants) = deserialize(&serialize(&procedures, &[])).unwrap();
+    assert!(constants.is_empty());
+    assert_eq!(without_locations(&decoded), without_locations(&procedures));
+    assert_eq!(run(&decoded), run(&procedures));
+}
+
+#[test]
+fn bad_bytecode_is_rejected() {
+    let bytes = serialize(&compile(PROGRAM), &[]);
+    assert!(deserialize(b"not bytecode").is_err());
+    assert!(deserialize(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn debug_bytecode_keeps_locations() {
+    let procedures = compile(PROGRAM);
+    let (decoded, _) = deserialize(&serialize_with_debug(&procedures, &[])).unwrap();
+    assert_eq!(decoded, procedures);
+
+    // a runtime error in loaded bytecode still points into the source
+    let procedures = compile("proc f (int) -> (int) { 1 swap / }\n0 f call print");
+    let (decoded, _) = deserialize(&serialize_with_debug(&procedures, &[])).unwrap();
+    let error = run_error(&decoded);
+    assert_eq!(error.location.filepath, "test.sbl");
+    assert_eq!((error.location.line, error.location.column), (1, 32));
+}
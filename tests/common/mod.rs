@@ -0,0 +1,54 @@
+#![allow(dead_code)]
+
+use sbl::{
+    common::SourceLocation,
+    compile_str,
+    ir_execution::{run_ir_with, RunOptions, Value},
+    Error, IRKind, IR,
+};
+
+/// Makes an instruction for IR that is built by hand
+pub fn ir(kind: IRKind) -> IR {
+    IR {
+        location: SourceLocation {
+            filepath: "<test>".to_string(),
+            position: 0,
+            line: 1,
+            column: 1,
+            length: 0,
+        },
+        kind,
+    }
+}
+
+pub fn compile(source: &str) -> Vec<Vec<IR>> {
+    compile_str("test.sbl", source).unwrap().procedures
+}
+
+pub fn compile_error(source: &str) -> Error {
+    compile_str("test.sbl", source).unwrap_err()
+}
+
+/// Runs `procedures` with `constants` as the constant pool, returning what was printed and the exit code
+pub fn run_with_constants(procedures: &[Vec<IR>], constants: &[Value]) -> (String, i64) {
+    let mut out = Vec::new();
+    let mut options = RunOptions::new(&mut out);
+    options.constants = constants;
+    let code = run_ir_with(procedures, &mut options).unwrap();
+    (String::from_utf8(out).unwrap(), code)
+}
+
+/// Runs `procedures` until it fails, returning the error
+pub fn run_error(procedures: &[Vec<IR>]) -> Error {
+    let mut out = Vec::new();
+    run_ir_with(procedures, &mut RunOptions::new(&mut out)).unwrap_err()
+}
+
+pub fn run(procedures: &[Vec<IR>]) -> (String, i64) {
+    run_with_constants(procedures, &[])
+}
+
+/// Compiles and runs `source`, returning what was printed
+pub fn output(source: &str) -> String {
+    run(&compile(source)).0
+}
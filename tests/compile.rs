@@ -0,0 +1,197 @@
+mod common;
+
+use common::{compile, compile_error, output};
+use sbl::IRKind;
+
+fn kinds(source: &str) -> Vec<IRKind> {
+    compile(source)[0]
+        .iter()
+        .map(|ir| ir.kind.clone())
+        .collect()
+}
+
+#[test]
+fn builtin_type_names_can_not_be_declared() {
+    let error = compile_error("proc int () {}");
+    assert_eq!(error.message, "Cannot redefine built-in type name 'int'");
+    assert_eq!((error.location.line, error.location.column), (1, 6));
+
+    for source in ["const bool 1", "alias type { int }", "proc float () {}"] {
+        let error = compile_error(source);
+        assert!(
+            error
+                .message
+                .starts_with("Cannot redefine built-in type name"),
+            "{}: {}",
+            source,
+            error.message
+        );
+    }
+}
+
+#[test]
+fn while_jumps_back_to_its_condition() {
+    assert_eq!(
+        kinds("0 while dup 5 < { 1 + } drop"),
+        vec![
+            IRKind::PushInt { value: 0 },
+            // condition
+            IRKind::Dup,
+            IRKind::PushInt { value: 5 },
+            IRKind::LessThan,
+            IRKind::JumpFalse {
+                relative_position: 4
+            },
+            // body
+            IRKind::PushInt { value: 1 },
+            IRKind::Add,
+            IRKind::Jump {
+                relative_position: -6
+            },
+            IRKind::Drop,
+            IRKind::Exit,
+        ]
+    );
+}
+
+#[test]
+fn consts_can_be_used_in_signatures_and_loop_bounds() {
+    let source = "
+const IntType { int }
+const N { 3 }
+proc count (IntType) -> (IntType) { while dup N < { 1 + } }
+0 count call print
+";
+    assert_eq!(output(source), "3\n");
+}
+
+#[test]
+fn unknown_names_are_errors() {
+    let error = compile_error("1 print\n  foobar");
+    assert_eq!(error.message, "Unable to find name 'foobar'");
+    assert_eq!((error.location.line, error.location.column), (2, 3));
+    assert_eq!(error.location.length, 6);
+}
+
+#[test]
+fn elif_chains_pick_one_branch() {
+    let source = "
+proc classify (int) -> () {
+    dup 0 < if { drop 1 print } elif dup 0 == { drop 2 print } else { drop 3 print }
+}
+5 neg classify call
+0 classify call
+5 classify call
+";
+    assert_eq!(output(source), "1\n2\n3\n");
+}
+
+#[test]
+fn unclosed_blocks_point_at_their_start() {
+    for (source, message, column) in [
+        (
+            "true if { 1 print",
+            "This 'if' is never closed with a '}'",
+            6,
+        ),
+        (
+            "true if { } else { 1 print",
+            "This 'else' is never closed with a '}'",
+            13,
+        ),
+        ("while true {", "This 'while' is never closed with a '}'", 1),
+        ("proc f () {", "This 'proc' is never closed with a '}'", 1),
+    ] {
+        let error = compile_error(source);
+        assert_eq!(error.message, message);
+        assert_eq!(error.location.column, column, "{}", source);
+    }
+}
+
+#[test]
+fn stray_close_brace_is_an_error() {
+    let error = compile_error("}");
+    assert_eq!(
+        error.message,
+        "Unexpected '}', there is no '{' for it to close"
+    );
+    assert_eq!(error.location.column, 1);
+
+    let error = compile_error("proc f () { } }");
+    assert_eq!(error.location.column, 15);
+}
+
+#[test]
+fn else_without_if() {
+    let error = compile_error("while 1 1 == {} else {}");
+    assert_eq!(error.message, "'else' without a matching 'if'");
+    assert_eq!(error.location.column, 17);
+}
+
+#[test]
+fn bare_parentheses_are_errors() {
+    for (source, message) in [("1 (", "Unexpected '('"), ("1 )", "Unexpected ')'")] {
+        let error = compile_error(source);
+        assert_eq!(error.message, message);
+        assert_eq!(error.location.column, 3);
+    }
+}
+
+#[test]
+fn stack_effect_comments_are_checked() {
+    assert_eq!(
+        output("#( a b -- int )\nproc add (int int) -> (int) { + }\n1 2 add call print"),
+        "3\n"
+    );
+
+    let error = compile_error("#( int bool -- int )\nproc add (int int) -> (int) { + }");
+    assert_eq!(
+        error.message,
+        "Stack effect 'int bool -- int' does not match the signature (int int) -> (int)"
+    );
+    assert_eq!((error.location.line, error.location.column), (1, 1));
+
+    let error = compile_error("#( a -- )\nproc add (int int) -> (int) { + }");
+    assert!(
+        error.message.starts_with("Stack effect"),
+        "{}",
+        error.message
+    );
+    assert_eq!(
+        compile_error("#( a b )\nproc add (int int) -> (int) { + }").message,
+        "Expected '--' in the stack effect comment"
+    );
+    assert_eq!(
+        compile_error("#( -- ) 1 print").message,
+        "Expected a stack effect comment to be directly before a 'proc'"
+    );
+}
+
+#[test]
+fn named_procedures_in_conditions() {
+    let source = "
+while proc p () -> (bool) { false } p call { 1 print }
+2 print
+false if { } elif proc q () -> (int) { 7 } q call 7 == { 3 print }
+q call print
+";
+    assert_eq!(output(source), "2\n3\n7\n");
+}
+
+#[test]
+fn consts_can_not_loop_forever_or_print() {
+    let error = compile_error("const X { while true { } 1 } X print");
+    assert_eq!(
+        error.message,
+        "Code run while compiling took more than 10000000 instructions"
+    );
+
+    let error = compile_error("const X { 5 print 1 } X print");
+    assert_eq!(
+        error.message,
+        "Unable to write output: code run while compiling can not print"
+    );
+    assert_eq!((error.location.line, error.location.column), (1, 13));
+
+    assert_eq!(output("const X { 2 3 * } X print"), "6\n");
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::compile_error;
+use sbl::common::render_error;
+
+#[test]
+fn render_error_underlines_the_whole_token() {
+    let source = "1 true ==";
+    assert_eq!(
+        render_error(source, &compile_error(source)),
+        "test.sbl:1:8: Cannot compare values of different types 'int' and 'bool'\n1 true ==\n       ^~"
+    );
+
+    let source = "\"abc\" undefined_name";
+    assert_eq!(
+        render_error(source, &compile_error(source)),
+        "test.sbl:1:7: Unable to find name 'undefined_name'\n\"abc\" undefined_name\n      ^~~~~~~~~~~~~~"
+    );
+}
+
+#[test]
+fn render_error_keeps_tabs_and_clamps_at_the_end() {
+    let source = "\t1 true +";
+    assert_eq!(
+        render_error(source, &compile_error(source)),
+        "test.sbl:1:9: Expected type 'int' or 'float', but got type 'bool'\n\t1 true +\n\t       ^"
+    );
+
+    let source = "1 0x";
+    assert_eq!(
+        render_error(source, &compile_error(source)),
+        "test.sbl:1:3: Missing digits after base prefix\n1 0x\n  ^"
+    );
+}
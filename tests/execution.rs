@@ -0,0 +1,429 @@
+mod common;
+
+use common::{compile, compile_error, ir, output, run_error};
+use sbl::{
+    compile::GlobalDeclarationKind,
+    compile_str,
+    ir_execution::{
+        run_ir_with, run_named, run_procedure, Breakpoint, Machine, OverflowMode, RunOptions,
+        StepResult, Value,
+    },
+    IRKind, Type,
+};
+
+#[test]
+fn run_named_uses_the_returned_declarations() {
+    let compiled = compile_str(
+        "test.sbl",
+        "proc square (int) -> (int) { dup * }\nproc unused () {}",
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    let stack = run_named(
+        &compiled.procedures,
+        &compiled.global_declarations,
+        "square",
+        vec![Value::Integer(7)],
+        &mut RunOptions::new(&mut out),
+    )
+    .unwrap();
+    assert_eq!(stack, vec![Value::Integer(49)]);
+}
+
+#[test]
+fn run_named_reports_a_missing_procedure() {
+    // there are no instructions to take a location from
+    let mut out = Vec::new();
+    let error = run_named(
+        &[],
+        &[],
+        "missing",
+        Vec::new(),
+        &mut RunOptions::new(&mut out),
+    )
+    .unwrap_err();
+    assert_eq!(error.message, "There is no procedure called 'missing'");
+}
+
+#[test]
+fn machine_runs_a_procedure_on_a_seeded_stack() {
+    let compiled = compile_str("test.sbl", "proc add (int int) -> (int) { + }").unwrap();
+    let id = compiled
+        .global_declarations
+        .iter()
+        .find_map(|declaration| match declaration.kind {
+            GlobalDeclarationKind::Proc { id } if declaration.name == "add" => Some(id),
+            _ => None,
+        })
+        .unwrap();
+
+    let mut out = Vec::new();
+    let mut machine = Machine::new(
+        &compiled.procedures,
+        id,
+        vec![Value::Integer(19), Value::Integer(23)],
+    );
+    let result = machine
+        .run(&compiled.procedures, &mut RunOptions::new(&mut out))
+        .unwrap();
+    assert_eq!(result, (StepResult::Halted(0), vec![Value::Integer(42)]));
+}
+
+#[test]
+fn location_breakpoints_only_stop_in_their_file() {
+    let directory = std::env::temp_dir().join("sbl_breakpoint_test");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("lib.sbl"), "2 print\n").unwrap();
+    let main_path = directory.join("main.sbl");
+    let compiled = compile_str(
+        &main_path.display().to_string(),
+        "1 print include \"lib.sbl\"\n",
+    )
+    .unwrap();
+
+    // both files have a `print` at 1:3
+    let mut out = Vec::new();
+    let mut machine = Machine::new(&compiled.procedures, 0, Vec::new());
+    machine.add_breakpoint(Breakpoint::Location {
+        filepath: directory.join("lib.sbl").display().to_string(),
+        line: 1,
+        column: 3,
+    });
+    let mut options = RunOptions::new(&mut out);
+    assert_eq!(
+        machine.run(&compiled.procedures, &mut options).unwrap(),
+        (StepResult::Breakpoint, vec![Value::Integer(2)])
+    );
+    assert_eq!(
+        machine.run(&compiled.procedures, &mut options).unwrap(),
+        (StepResult::Halted(0), vec![])
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n");
+}
+
+#[test]
+fn loop_prints_one_to_five() {
+    let source = "1 while dup 5 <= { dup print 1 + } drop";
+    let procedures = compile_str("test.sbl", source).unwrap().procedures;
+    let mut out = Vec::new();
+    let code = run_ir_with(&procedures, &mut RunOptions::new(&mut out)).unwrap();
+    assert_eq!(code, 0);
+    assert_eq!(String::from_utf8(out).unwrap(), "1\n2\n3\n4\n5\n");
+}
+
+#[test]
+fn signature_code_returns_its_types() {
+    // what `compile_ir` runs for the parameter list `(int bool)`
+    let procedures = vec![vec![
+        ir(IRKind::PushType { typ: Type::Integer }),
+        ir(IRKind::PushType { typ: Type::Bool }),
+        ir(IRKind::Exit),
+    ]];
+    let mut out = Vec::new();
+    let stack = run_procedure(&procedures, 0, &mut RunOptions::new(&mut out)).unwrap();
+    assert_eq!(
+        stack,
+        vec![Value::Type(Type::Integer), Value::Type(Type::Bool)]
+    );
+
+    let compiled = compile_str("test.sbl", "proc f (int bool) -> (bool) { nip }").unwrap();
+    assert!(compiled.signatures.contains(&Type::Procedure {
+        parameters: vec![Type::Integer, Type::Bool],
+        return_types: vec![Type::Bool],
+    }));
+}
+
+#[test]
+fn calls_return_to_the_caller() {
+    assert_eq!(
+        output("proc double (int) -> (int) { 2 * } 21 double call print 1 print"),
+        "42\n1\n"
+    );
+}
+
+#[test]
+fn division_by_zero_is_a_runtime_error() {
+    let procedures = vec![vec![
+        ir(IRKind::PushInt { value: 5 }),
+        ir(IRKind::PushInt { value: 0 }),
+        ir(IRKind::Div),
+        ir(IRKind::Exit),
+    ]];
+    assert_eq!(run_error(&procedures).message, "Division by zero");
+
+    // the divisor is only known when the program runs
+    let error = run_error(&compile(
+        "proc f (int) -> (int) { 5 swap % } 0 f call print",
+    ));
+    assert_eq!(error.message, "Division by zero");
+    assert_eq!(error.location.column, 32);
+}
+
+#[test]
+fn modulo() {
+    assert_eq!(output("7 3 % print 7 neg 3 % print"), "1\n-1\n");
+}
+
+#[test]
+fn bitwise_operators() {
+    assert_eq!(output("6 3 & print 6 3 | print 6 3 ^ print"), "2\n7\n5\n");
+}
+
+#[test]
+fn over_and_rot() {
+    assert_eq!(output("1 2 over .s"), "<3> 1 2 1\n");
+    assert_eq!(output("1 2 3 rot .s"), "<3> 2 3 1\n");
+}
+
+#[test]
+fn stack_underflow_is_an_error() {
+    let error = run_error(&[vec![ir(IRKind::Add), ir(IRKind::Exit)]]);
+    assert_eq!(error.message, "Stack underflow");
+}
+
+#[test]
+fn running_past_the_end_is_an_error() {
+    let error = run_error(&[vec![]]);
+    assert_eq!(
+        error.message,
+        "Execution went outside of procedure 0 at instruction 0"
+    );
+
+    let error = run_error(&[vec![ir(IRKind::PushInt { value: 1 }), ir(IRKind::Print)]]);
+    assert_eq!(
+        error.message,
+        "Execution went outside of procedure 0 at instruction 2"
+    );
+
+    let error = run_error(&[]);
+    assert_eq!(error.message, "Procedure 0 does not exist");
+}
+
+#[test]
+fn two_dup_nip_and_tuck() {
+    assert_eq!(output("1 2 2dup .s"), "<4> 1 2 1 2\n");
+    assert_eq!(output("1 2 nip .s"), "<1> 2\n");
+    assert_eq!(output("1 2 tuck .s"), "<3> 2 1 2\n");
+    assert_eq!(output("1 true 2dup .s"), "<4> 1 true 1 true\n");
+}
+
+#[test]
+fn neg_pushes_negative_numbers() {
+    assert_eq!(
+        output("5 neg print 10 3 - print 5 neg neg print"),
+        "-5\n7\n5\n"
+    );
+}
+
+#[test]
+fn and_or_on_comparisons() {
+    assert_eq!(
+        output("3 2 > 1 1 == and print 3 2 < 1 1 == and print 3 2 < 1 1 == or print false false or print"),
+        "true\nfalse\ntrue\nfalse\n"
+    );
+}
+
+#[test]
+fn unbounded_recursion_hits_the_limit() {
+    let procedures = compile("proc forever () { forever call } forever call");
+    let mut out = Vec::new();
+    let mut options = RunOptions::new(&mut out);
+    options.max_recursion_depth = 1000;
+    let error = run_ir_with(&procedures, &mut options).unwrap_err();
+    assert_eq!(error.message, "Maximum recursion depth exceeded");
+    assert_eq!(error.location.column, 27);
+}
+
+#[test]
+fn fuel_stops_infinite_loops() {
+    let procedures = compile("while 1 1 == { }");
+    let mut out = Vec::new();
+    let mut options = RunOptions::new(&mut out);
+    options.fuel = Some(100);
+    let error = run_ir_with(&procedures, &mut options).unwrap_err();
+    assert_eq!(error.message, "Execution budget exhausted");
+
+    // a program that finishes within its budget is not affected
+    let procedures = compile("1 print");
+    let mut options = RunOptions::new(&mut out);
+    options.fuel = Some(3);
+    assert_eq!(run_ir_with(&procedures, &mut options).unwrap(), 0);
+}
+
+#[test]
+fn comparing_different_types_is_a_runtime_error() {
+    for kind in [IRKind::Equal, IRKind::NotEqual] {
+        let procedures = vec![vec![
+            ir(IRKind::PushInt { value: 1 }),
+            ir(IRKind::PushBool { value: true }),
+            ir(kind),
+            ir(IRKind::Exit),
+        ]];
+        assert_eq!(
+            run_error(&procedures).message,
+            "Cannot compare values of different types"
+        );
+    }
+}
+
+#[test]
+fn emit_has_no_newline() {
+    assert_eq!(output("1 emit 2 print"), "12\n");
+    assert_eq!(output("true emit 1.5 print"), "true1.5\n");
+}
+
+#[test]
+fn overflow_wraps_or_fails() {
+    let run_with = |source: &str, overflow: OverflowMode| {
+        let procedures = compile(source);
+        let mut out = Vec::new();
+        let mut options = RunOptions::new(&mut out);
+        options.overflow = overflow;
+        run_ir_with(&procedures, &mut options).map(|_| String::from_utf8(out).unwrap())
+    };
+
+    let max = i64::MAX;
+    let min = format!("{} neg 1 -", max);
+    for (source, wrapped) in [
+        (format!("{} 1 + print", max), i64::MIN),
+        (format!("{} 1 - print", min), i64::MAX),
+        (format!("{} 2 * print", max), -2),
+    ] {
+        assert_eq!(
+            run_with(&source, OverflowMode::Wrapping).unwrap(),
+            format!("{}\n", wrapped)
+        );
+        let error = run_with(&source, OverflowMode::Checked).unwrap_err();
+        assert_eq!(error.message, "Integer overflow", "{}", source);
+    }
+    assert_eq!(
+        run_with(&format!("{} 1 - 1 + print", max), OverflowMode::Checked).unwrap(),
+        format!("{}\n", max)
+    );
+}
+
+#[test]
+fn assert_fails_on_false() {
+    assert_eq!(output("2 2 == assert 1 print"), "1\n");
+
+    let error = run_error(&compile("1 print\n1 2 == assert"));
+    assert_eq!(error.message, "Assertion failed");
+    assert_eq!((error.location.line, error.location.column), (2, 8));
+}
+
+#[test]
+fn max_and_min() {
+    assert_eq!(
+        output("3 7 max print 3 7 min print 5 neg 2 max print"),
+        "7\n3\n2\n"
+    );
+}
+
+#[test]
+fn values_round_trip_through_memory() {
+    assert_eq!(
+        output("memory cell 8\ncell 42 <- cell @ print\ncell cell @ 1 + <- cell @ print"),
+        "42\n43\n"
+    );
+}
+
+#[test]
+fn print_in_other_bases() {
+    assert_eq!(
+        output("255 print 255 print_hex 255 print_bin 5 neg print_hex"),
+        "255\n0xff\n0b11111111\n-0x5\n"
+    );
+    for source in ["true print_hex", "true print_bin"] {
+        assert_eq!(
+            compile_error(source).message,
+            "Expected type 'int', but got type 'bool'"
+        );
+    }
+}
+
+#[test]
+fn pick_and_roll() {
+    assert_eq!(output("1 2 3 2 pick .s"), "<4> 1 2 3 1\n");
+    assert_eq!(output("1 2 3 2 roll .s"), "<3> 2 3 1\n");
+    assert_eq!(output("1 2 3 0 roll .s"), "<3> 1 2 3\n");
+
+    assert_eq!(
+        compile_error("1 2 5 pick").message,
+        "Expected at least 6 values on the stack, but got 2"
+    );
+    assert_eq!(
+        compile_error("1 2 dup roll").message,
+        "Expected 'roll' to be preceded by a constant depth"
+    );
+}
+
+#[test]
+fn pick_and_roll_depth_can_not_come_from_a_branch() {
+    for source in [
+        "1 2 3 true if { 2 } else { 2 } pick print",
+        "1 2 3 true if { 2 } pick print",
+        "1 2 3 true if { 2 } else { 2 } roll .s",
+    ] {
+        let error = compile_error(source);
+        assert!(
+            error
+                .message
+                .ends_with("to be preceded by a constant depth"),
+            "{}: {}",
+            source,
+            error.message
+        );
+    }
+    // a `!` at the end of a branch is not folded into the condition after it either
+    assert_eq!(
+        output("false true if { ! } if { 1 print } 2 print"),
+        "1\n2\n"
+    );
+    assert_eq!(
+        output("true if { 1 2 3 2 pick .s drop drop drop drop }"),
+        "<4> 1 2 3 1\n"
+    );
+}
+
+#[test]
+fn machine_can_be_stepped() {
+    let procedures = compile("1 2 + print");
+    let mut out = Vec::new();
+    let mut options = RunOptions::new(&mut out);
+    let mut machine = Machine::new(&procedures, 0, Vec::new());
+
+    for _ in 0..2 {
+        assert_eq!(
+            machine.step(&procedures, &mut options).unwrap(),
+            StepResult::Continue
+        );
+    }
+    assert_eq!(machine.stack, vec![Value::Integer(1), Value::Integer(2)]);
+    assert_eq!(machine.ip, 2);
+
+    machine.step(&procedures, &mut options).unwrap();
+    assert_eq!(machine.stack, vec![Value::Integer(3)]);
+
+    assert_eq!(
+        machine.run(&procedures, &mut options).unwrap(),
+        (StepResult::Halted(0), vec![])
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), "3\n");
+}
+
+#[test]
+fn dropn_and_dupn() {
+    assert_eq!(output("1 2 3 2 dropn .s"), "<1> 1\n");
+    assert_eq!(output("1 2 2 dupn .s"), "<4> 1 2 1 2\n");
+    assert_eq!(output("1 2 0 dupn .s"), "<2> 1 2\n");
+
+    // the count has to be known when compiling, a count from a branch is not
+    assert_eq!(
+        compile_error("10 true if { 1 } else { 2 } dupn").message,
+        "Expected 'dupn' to be preceded by a constant count"
+    );
+    assert_eq!(
+        compile_error("5 6 true if { 1 } else { 1 } dropn print").message,
+        "Expected 'dropn' to be preceded by a constant count"
+    );
+}
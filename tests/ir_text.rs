@@ -0,0 +1,42 @@
+mod common;
+
+use common::{compile, run};
+use sbl::{
+    ir_text::{dump, parse},
+    IRKind, IR,
+};
+
+fn without_locations(procedures: &[Vec<IR>]) -> Vec<Vec<IRKind>> {
+    procedures
+        .iter()
+        .map(|procedure| procedure.iter().map(|ir| ir.kind.clone()).collect())
+        .collect()
+}
+
+#[test]
+fn dump_then_parse_round_trips() {
+    let procedures = compile(
+        "
+memory cell 8
+proc fib (int) -> (int) {
+    dup 2 < if { } else { dup 1 - fib call swap 2 - fib call + }
+}
+cell 10 fib call <-
+cell @ print
+1.5 2.5 + print
+\"text\\n\" print_str
+bool print
+0 while dup 3 < { 1 + } 5 1 pick print drop drop
+",
+    );
+    let parsed = parse(&dump(&procedures)).unwrap();
+    assert_eq!(without_locations(&parsed), without_locations(&procedures));
+    assert_eq!(run(&parsed), run(&procedures));
+}
+
+#[test]
+fn unknown_mnemonics_are_rejected() {
+    let error = parse(".proc 0\n0: push_int 1\n1: frobnicate\n2: exit\n").unwrap_err();
+    assert_eq!(error.message, "Unknown mnemonic 'frobnicate'");
+    assert_eq!((error.location.line, error.location.column), (3, 4));
+}
@@ -0,0 +1,133 @@
+use sbl::{
+    lexer::Lexer,
+    token::{Token, TokenData, TokenKind},
+    tokenizer::Tokenizer,
+    Error,
+};
+
+fn lex_with(mut lexer: Lexer) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        if token.kind == TokenKind::EndOfFile {
+            return Ok(tokens);
+        }
+        tokens.push(token);
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, Error> {
+    lex_with(Lexer::new("test.sbl".to_string(), source))
+}
+
+#[test]
+fn base_prefixes_need_digits() {
+    for source in ["0x", "0b", "0o", "1 0x "] {
+        let error = lex(source).unwrap_err();
+        assert_eq!(
+            error.message, "Missing digits after base prefix",
+            "{}",
+            source
+        );
+    }
+    assert_eq!(lex("1 0x").unwrap_err().location.column, 3);
+
+    let tokens = lex("0b10 0o17 0xff 0").unwrap();
+    let values: Vec<TokenData> = tokens.into_iter().map(|token| token.data).collect();
+    assert_eq!(
+        values,
+        vec![
+            TokenData::Integer(2),
+            TokenData::Integer(15),
+            TokenData::Integer(255),
+            TokenData::Integer(0),
+        ]
+    );
+}
+
+#[test]
+fn columns_follow_tabs_and_crlf() {
+    let positions = |lexer: Lexer| -> Vec<(usize, usize)> {
+        lex_with(lexer)
+            .unwrap()
+            .into_iter()
+            .map(|token| (token.location.line, token.location.column))
+            .collect()
+    };
+    let source = "1\r\n\t2 3\r\n  \t4";
+
+    assert_eq!(
+        positions(Lexer::new("test.sbl".to_string(), source)),
+        vec![(1, 1), (2, 2), (2, 4), (3, 4)]
+    );
+    assert_eq!(
+        positions(Lexer::with_tab_width("test.sbl".to_string(), source, 4)),
+        vec![(1, 1), (2, 5), (2, 7), (3, 5)]
+    );
+}
+
+fn kinds(source: &str) -> Vec<TokenKind> {
+    lex(source)
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect()
+}
+
+#[test]
+fn operators_take_the_longest_match() {
+    assert_eq!(
+        kinds("< <= << <<= -> - >"),
+        vec![
+            TokenKind::LessThan,
+            TokenKind::LessThanEqual,
+            TokenKind::ShiftLeft,
+            // there is no `<<=`, so it is the longest operator that matches followed by the rest
+            TokenKind::ShiftLeft,
+            TokenKind::Equal,
+            TokenKind::RightArrow,
+            TokenKind::Minus,
+            TokenKind::GreaterThan,
+        ]
+    );
+    assert_eq!(
+        kinds("1<=2"),
+        vec![
+            TokenKind::Integer,
+            TokenKind::LessThanEqual,
+            TokenKind::Integer
+        ]
+    );
+}
+
+#[test]
+fn underscores_must_be_between_digits() {
+    assert_eq!(lex("1_000").unwrap()[0].data, TokenData::Integer(1000));
+    assert_eq!(lex("0xff_ff").unwrap()[0].data, TokenData::Integer(0xffff));
+
+    for (source, column) in [("1__0", 2), ("1_", 2), ("0x_1", 3)] {
+        let error = lex(source).unwrap_err();
+        assert_eq!(error.message, "'_' in a number must be between two digits");
+        assert_eq!(error.location.column, column, "{}", source);
+    }
+}
+
+#[test]
+fn peeking_gives_the_next_token() {
+    let source = "proc p (int) -> (int) {\n\t\"ü\" drop 0x1F +\r\n} $";
+    let expected = lex(&source[..source.len() - 1]).unwrap();
+
+    let mut lexer = Lexer::new("test.sbl".to_string(), source);
+    for token in &expected {
+        assert_eq!(lexer.peek_kind().unwrap(), token.kind);
+        assert_eq!(&lexer.peek_token().unwrap(), token);
+        assert_eq!(&lexer.peek_nth(0).unwrap(), token);
+        assert_eq!(&lexer.next_token().unwrap(), token);
+    }
+
+    // errors are cached the same way as tokens
+    let error = lexer.peek_token().unwrap_err();
+    assert_eq!(error.message, "Unknown character '$'");
+    assert_eq!(lexer.next_token().unwrap_err(), error);
+    assert_eq!(lexer.next_token().unwrap().kind, TokenKind::EndOfFile);
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use common::{compile, ir, output, run};
+use sbl::{
+    ir_execution::{run_ir_with, RunOptions},
+    optimize, IRKind, IR,
+};
+
+fn kinds(procedure: &[IR]) -> Vec<IRKind> {
+    procedure.iter().map(|ir| ir.kind.clone()).collect()
+}
+
+#[test]
+fn constant_false_if_leaves_no_trace() {
+    let mut procedures = compile("false if { 1 print } 2 print\nproc unused () {}");
+    optimize::run(&mut procedures, 2);
+    assert_eq!(procedures.len(), 1);
+    assert_eq!(
+        kinds(&procedures[0]),
+        vec![IRKind::PushInt { value: 2 }, IRKind::Print, IRKind::Exit]
+    );
+}
+
+#[test]
+fn dead_code_elimination_fixes_jumps_over_removed_code() {
+    let mut procedures = vec![vec![
+        ir(IRKind::Jump {
+            relative_position: 3,
+        }),
+        ir(IRKind::PushInt { value: 1 }),
+        ir(IRKind::Print),
+        ir(IRKind::PushInt { value: 2 }),
+        ir(IRKind::Print),
+        ir(IRKind::Exit),
+    ]];
+    optimize::eliminate_dead_code(&mut procedures);
+    assert_eq!(
+        kinds(&procedures[0]),
+        vec![
+            IRKind::Jump {
+                relative_position: 1
+            },
+            IRKind::PushInt { value: 2 },
+            IRKind::Print,
+            IRKind::Exit,
+        ]
+    );
+}
+
+#[test]
+fn tail_calls_run_in_constant_depth() {
+    let source = "
+proc countdown (int) -> () { dup 0 == if { drop } else { 1 - countdown call } }
+1000 countdown call
+";
+    let run_with_depth = |procedures: &[Vec<IR>]| {
+        let mut out = Vec::new();
+        let mut options = RunOptions::new(&mut out);
+        options.max_recursion_depth = 10;
+        run_ir_with(procedures, &mut options)
+    };
+
+    let mut procedures = compile(source);
+    assert_eq!(
+        run_with_depth(&procedures).unwrap_err().message,
+        "Maximum recursion depth exceeded"
+    );
+    optimize::optimize_tail_calls(&mut procedures);
+    assert!(procedures
+        .iter()
+        .flatten()
+        .any(|ir| ir.kind == IRKind::TailCall));
+    assert_eq!(run_with_depth(&procedures).unwrap(), 0);
+}
+
+fn peephole(kinds: Vec<IRKind>) -> Vec<IRKind> {
+    let mut procedures = vec![kinds.into_iter().map(ir).collect::<Vec<_>>()];
+    optimize::peephole(&mut procedures);
+    self::kinds(&procedures[0])
+}
+
+#[test]
+fn peephole_removes_pairs_that_cancel_out() {
+    for pair in [
+        [IRKind::Dup, IRKind::Drop],
+        [IRKind::Swap, IRKind::Swap],
+        [IRKind::Not, IRKind::Not],
+        [IRKind::PushInt { value: 1 }, IRKind::Drop],
+        [IRKind::PushBool { value: true }, IRKind::Drop],
+    ] {
+        let mut kinds = vec![IRKind::PushBool { value: false }];
+        kinds.extend(pair.clone());
+        kinds.push(IRKind::Exit);
+        assert_eq!(
+            peephole(kinds),
+            vec![IRKind::PushBool { value: false }, IRKind::Exit],
+            "{:?}",
+            pair
+        );
+    }
+
+    // removing one pair can make another
+    assert_eq!(
+        peephole(vec![
+            IRKind::Swap,
+            IRKind::Dup,
+            IRKind::Drop,
+            IRKind::Swap,
+            IRKind::Exit,
+        ]),
+        vec![IRKind::Exit]
+    );
+}
+
+#[test]
+fn peephole_fixes_jumps_and_keeps_jump_targets() {
+    assert_eq!(
+        peephole(vec![
+            IRKind::PushBool { value: true },
+            IRKind::JumpFalse {
+                relative_position: 3,
+            },
+            IRKind::Dup,
+            IRKind::Drop,
+            IRKind::Print,
+            IRKind::Exit,
+        ]),
+        vec![
+            IRKind::PushBool { value: true },
+            IRKind::JumpFalse {
+                relative_position: 1,
+            },
+            IRKind::Print,
+            IRKind::Exit,
+        ]
+    );
+
+    // the jump lands on the `drop`, so the `dup` before it is still needed
+    let kinds = vec![
+        IRKind::PushInt { value: 1 },
+        IRKind::PushBool { value: true },
+        IRKind::JumpFalse {
+            relative_position: 2,
+        },
+        IRKind::Dup,
+        IRKind::Drop,
+        IRKind::Exit,
+    ];
+    assert_eq!(peephole(kinds.clone()), kinds);
+}
+
+fn jumps_to_jumps(procedure: &[IR]) -> usize {
+    procedure
+        .iter()
+        .enumerate()
+        .filter(|(position, ir)| match ir.kind {
+            IRKind::Jump { relative_position } | IRKind::JumpFalse { relative_position } => {
+                matches!(
+                    procedure[(*position as isize + relative_position) as usize].kind,
+                    IRKind::Jump { .. }
+                )
+            }
+            _ => false,
+        })
+        .count()
+}
+
+#[test]
+fn jump_chains_are_collapsed() {
+    // the end of the `if` jumps to the end of the `else`, which jumps back to the condition
+    let source = "0 while dup 3 < { 1 + dup 2 == if { 1 print } else { 2 print } } drop";
+    let mut procedures = compile(source);
+    assert_eq!(jumps_to_jumps(&procedures[0]), 1);
+    let expected = run(&procedures);
+
+    optimize::thread_jumps(&mut procedures);
+    assert_eq!(jumps_to_jumps(&procedures[0]), 0);
+    assert_eq!(run(&procedures), expected);
+
+    // a jump to itself is left alone
+    let mut procedures = vec![vec![
+        ir(IRKind::Jump {
+            relative_position: 0,
+        }),
+        ir(IRKind::Exit),
+    ]];
+    optimize::thread_jumps(&mut procedures);
+    assert_eq!(
+        procedures[0][0].kind,
+        IRKind::Jump {
+            relative_position: 0
+        }
+    );
+}
+
+#[test]
+fn swap_is_only_removed_before_commutative_operators() {
+    let optimized = |source: &str| {
+        let mut procedures = compile(source);
+        optimize::peephole(&mut procedures);
+        kinds(&procedures[0])
+    };
+    assert_eq!(
+        optimized("5 3 swap + print"),
+        vec![
+            IRKind::PushInt { value: 5 },
+            IRKind::PushInt { value: 3 },
+            IRKind::Add,
+            IRKind::Print,
+            IRKind::Exit,
+        ]
+    );
+    for source in ["5 3 swap - print", "5 3 swap / print", "5 3 swap < print"] {
+        assert!(optimized(source).contains(&IRKind::Swap), "{}", source);
+    }
+    assert_eq!(output("5 3 swap - print"), "-2\n");
+}
+
+#[test]
+fn constant_comparisons_fold_branches() {
+    for (source, value) in [
+        ("5 3 > if { 1 } else { 2 } print", 1),
+        ("5 3 <= if { 1 } else { 2 } print", 2),
+        ("5 5 == if { 1 } else { 2 } print", 1),
+        ("5 5 != if { 1 } else { 2 } print", 2),
+    ] {
+        let mut procedures = compile(source);
+        optimize::run(&mut procedures, 2);
+        assert_eq!(
+            kinds(&procedures[0]),
+            vec![IRKind::PushInt { value }, IRKind::Print, IRKind::Exit],
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn small_procedures_are_inlined() {
+    let source = "proc inc (int) -> (int) { 1 + } 41 inc call print";
+    let mut procedures = compile(source);
+    optimize::inline(&mut procedures, optimize::INLINE_THRESHOLD);
+    assert_eq!(
+        kinds(&procedures[0]),
+        vec![
+            IRKind::PushInt { value: 41 },
+            IRKind::PushInt { value: 1 },
+            IRKind::Add,
+            IRKind::Print,
+            IRKind::Exit,
+        ]
+    );
+    assert_eq!(run(&procedures).0, "42\n");
+
+    // a procedure used as a value is still called through the pointer
+    let mut procedures = compile("proc inc (int) -> (int) { 1 + } inc 41 swap call print");
+    optimize::inline(&mut procedures, optimize::INLINE_THRESHOLD);
+    assert!(procedures[0].iter().any(|ir| ir.kind == IRKind::Call));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{compile, compile_error, output};
+use sbl::{compile_str, optimize};
+
+#[test]
+fn division_by_a_constant_zero_is_rejected_at_every_level() {
+    for level in 0..=2 {
+        for (source, column) in [("10 0 / print", 6), ("10 0 % print", 6)] {
+            let error = compile_str("test.sbl", source)
+                .map(|compiled| {
+                    let mut procedures = compiled.procedures;
+                    optimize::run(&mut procedures, level);
+                })
+                .unwrap_err();
+            assert_eq!(error.message, "Division by zero");
+            assert_eq!(
+                (error.location.line, error.location.column),
+                (1, column),
+                "-O{}",
+                level
+            );
+        }
+    }
+}
+
+#[test]
+fn division_by_a_computed_zero_is_not_rejected() {
+    compile("proc f (int) -> (int) { 10 swap / } 0 f call print");
+}
+
+#[test]
+fn recursive_factorial() {
+    let source = "
+proc factorial (int) -> (int) {
+    dup 1 <= if { drop 1 } else { dup 1 - factorial call * }
+}
+10 factorial call print
+";
+    assert_eq!(output(source), "3628800\n");
+
+    // the recursive call is checked against the declared signature
+    let error = compile_error("proc f (int) -> (int) { dup 0 == if { } else { 1 - f call drop } }");
+    assert_eq!(
+        error.message,
+        "Expected (int) on the stack at the end of the procedure, but got ()"
+    );
+}
+
+#[test]
+fn mutual_recursion() {
+    let source = "
+proc ping (int) -> () { dup print dup 0 == if { drop } else { 1 - pong call } }
+proc pong (int) -> () { dup neg print dup 0 == if { drop } else { 1 - ping call } }
+3 ping call
+";
+    assert_eq!(output(source), "3\n-2\n1\n0\n");
+}
+
+#[test]
+fn equality_needs_matching_types() {
+    for source in ["1 true ==", "1 true !="] {
+        let error = compile_error(source);
+        assert_eq!(
+            error.message,
+            "Cannot compare values of different types 'int' and 'bool'"
+        );
+        assert_eq!(error.location.column, 8);
+    }
+    assert_eq!(output("true true == print 1 2 != print"), "true\ntrue\n");
+}
+
+#[test]
+fn every_path_to_the_end_is_checked() {
+    let error = compile_error("proc f (int) -> () { dup 0 == if { drop } }");
+    assert_eq!(
+        error.message,
+        "Expected () on the stack at the end of the procedure, but got (int)"
+    );
+    assert_eq!(error.location.column, 31);
+
+    // a path that never reaches the end does not matter
+    compile("proc g () -> (int) { 1 exit }");
+}
@@ -0,0 +1,44 @@
+use sbl::Type;
+
+fn procedure(parameters: Vec<Type>, return_types: Vec<Type>) -> Type {
+    Type::Procedure {
+        parameters,
+        return_types,
+    }
+}
+
+#[test]
+fn types_sort_in_a_fixed_order() {
+    let mut types = vec![
+        procedure(vec![Type::Integer], vec![Type::Bool]),
+        Type::Bool,
+        procedure(
+            vec![procedure(vec![], vec![Type::Integer])],
+            vec![Type::Integer],
+        ),
+        procedure(vec![Type::Integer], vec![]),
+        Type::Float,
+        procedure(vec![], vec![Type::Type]),
+        Type::Integer,
+        Type::Type,
+        procedure(vec![Type::Integer, Type::Integer], vec![]),
+    ];
+    types.sort();
+    assert_eq!(
+        types,
+        vec![
+            Type::Type,
+            Type::Integer,
+            Type::Float,
+            Type::Bool,
+            procedure(vec![], vec![Type::Type]),
+            procedure(vec![Type::Integer], vec![]),
+            procedure(vec![Type::Integer], vec![Type::Bool]),
+            procedure(vec![Type::Integer, Type::Integer], vec![]),
+            procedure(
+                vec![procedure(vec![], vec![Type::Integer])],
+                vec![Type::Integer],
+            ),
+        ]
+    );
+}